@@ -1,13 +1,13 @@
 extern crate clap;
-extern crate serde;
-extern crate serde_json;
-extern crate serde_yaml;
 extern crate raytracer;
 extern crate image;
 
 use clap::{Arg, App};
-use std::fs::{File, OpenOptions};
+use std::fs::OpenOptions;
+use std::process;
+use std::str::FromStr;
 use raytracer::scene::*;
+use raytracer::hdr::HdrFormat;
 use image::ImageFormat;
 
 fn main() {
@@ -20,24 +20,74 @@ fn main() {
       .required(true)
       .index(1))
     .arg(Arg::with_name("image")
-      .help("Sets the output image file")
+      .help("Sets the output image file (.hdr, .pfm or .exr for floating-point output)")
       .required(true)
-      .index(2));
+      .index(2))
+    .arg(Arg::with_name("samples")
+      .help("Sets the number of samples per pixel")
+      .short("s")
+      .long("samples")
+      .takes_value(true))
+    .arg(Arg::with_name("threads")
+      .help("Sets the number of render threads (defaults to one per CPU)")
+      .short("j")
+      .long("threads")
+      .takes_value(true))
+    .arg(Arg::with_name("tonemap")
+      .help("Sets the tone mapper: exposure, reinhard or aces")
+      .long("tonemap")
+      .takes_value(true))
+    .arg(Arg::with_name("exposure")
+      .help("Sets the exposure adjustment in stops")
+      .long("exposure")
+      .takes_value(true)
+      .allow_hyphen_values(true));
   
   let matches = app.get_matches();
 
   let scene_path = matches.value_of("scene").unwrap();
-  let scene_file = File::open(scene_path).expect("File not found");
-
   let image_path = matches.value_of("image").unwrap();
 
-  let scene: Scene = if scene_path.ends_with(".yml") || scene_path.ends_with(".yaml") {
-    serde_yaml::from_reader(scene_file).unwrap()
-  } else if scene_path.ends_with(".json") {
-    serde_json::from_reader(scene_file).unwrap()
-  } else {
-    panic!("Invalid scene file type! Must be .json, .yml, or .yaml");
+  let mut scene = match Scene::load(scene_path) {
+    Ok(scene) => scene,
+    Err(e) => {
+      eprintln!("error: {}", e);
+      process::exit(1);
+    }
   };
+  if let Some(samples) = matches.value_of("samples") {
+    scene.samples_per_pixel = parse_arg(samples, "sample count");
+    if scene.samples_per_pixel == 0 {
+      eprintln!("error: sample count must be at least 1");
+      process::exit(1);
+    }
+  }
+  if let Some(threads) = matches.value_of("threads") {
+    scene.threads = parse_arg(threads, "thread count");
+    if scene.threads == 0 {
+      eprintln!("error: thread count must be at least 1");
+      process::exit(1);
+    }
+  }
+  if let Some(tone_mapper) = matches.value_of("tonemap") {
+    scene.tone_mapper = tone_mapper.parse().unwrap_or_else(|e| {
+      eprintln!("error: {}", e);
+      process::exit(1);
+    });
+  }
+  if let Some(exposure) = matches.value_of("exposure") {
+    scene.exposure = parse_arg(exposure, "exposure");
+  }
+
+  // Floating-point formats keep the full range of the rendered radiance.
+  if let Some(format) = HdrFormat::from_path(image_path) {
+    let image = raytracer::render_hdr(&scene);
+    if let Err(e) = format.save(image_path, &image) {
+      eprintln!("error: unable to write {}: {}", image_path, e);
+      process::exit(1);
+    }
+    return;
+  }
 
   let image = raytracer::render(&scene);
 
@@ -45,3 +95,11 @@ fn main() {
     OpenOptions::new().write(true).truncate(true).create(true).open(image_path).unwrap();
   image.save(&mut image_file, ImageFormat::PNG).unwrap();
 }
+
+/// Parses the value of a command line option, or exits with an error naming it.
+fn parse_arg<T: FromStr>(value: &str, name: &str) -> T {
+  value.parse().unwrap_or_else(|_| {
+    eprintln!("error: invalid {} '{}'", name, value);
+    process::exit(1);
+  })
+}
@@ -0,0 +1,200 @@
+//! The GGX (Trowbridge-Reitz) microfacet BRDF behind `SurfaceType::Principled`, with
+//! Smith shadowing-masking and Schlick's Fresnel approximation.
+//!
+//! Directions all point away from the surface: `outgoing` towards the viewer and `incoming`
+//! towards the light.
+
+use scene::Color;
+use vector::Vector3;
+use sampling::{Rng, cosine_hemisphere};
+use std::f64::consts::PI;
+
+/// Roughness is squared into the GGX width, which is kept above this so that a perfectly
+/// smooth surface still has a finite highlight.
+const MIN_ALPHA: f64 = 1e-3;
+
+/// The GGX width for a perceptual roughness in [0, 1].
+pub fn roughness_to_alpha(roughness: f32) -> f64 {
+  let roughness = roughness.clamp(0.0, 1.0) as f64;
+  (roughness * roughness).max(MIN_ALPHA)
+}
+
+/// Draws a microfacet normal around `normal` in proportion to the GGX distribution of width
+/// `alpha` times its cosine to `normal`.
+pub fn sample_ggx_normal(normal: &Vector3, alpha: f64, rng: &mut Rng) -> Vector3 {
+  let (u1, u2) = (rng.next_f64(), rng.next_f64());
+  let tan2 = alpha * alpha * u1 / (1.0 - u1);
+  let cos = (1.0 + tan2).sqrt().recip();
+  let sin = (1.0 - cos * cos).max(0.0).sqrt();
+  let (sin_phi, cos_phi) = (2.0 * PI * u2).sin_cos();
+  let (tangent, bitangent) = normal.orthonormal_basis();
+  tangent * (sin * cos_phi) + bitangent * (sin * sin_phi) + *normal * cos
+}
+
+/// A direction chosen by sampling the BRDF, with the BRDF times the cosine term divided by
+/// the probability of choosing it.
+#[derive(Debug, Clone, Copy)]
+pub struct BrdfSample {
+  pub direction: Vector3,
+  pub weight: Color,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+  diffuse: Color,
+  f0: Color,
+  alpha: f64,
+}
+impl Principled {
+  pub fn new(base_color: Color, metallic: f32, roughness: f32, specular: f32) -> Principled {
+    let metallic = metallic.clamp(0.0, 1.0);
+    let dielectric = 0.08 * specular.max(0.0);
+    Principled {
+      diffuse: base_color * (1.0 - metallic),
+      f0: Color {
+        red: dielectric + (base_color.red - dielectric) * metallic,
+        green: dielectric + (base_color.green - dielectric) * metallic,
+        blue: dielectric + (base_color.blue - dielectric) * metallic,
+      },
+      alpha: roughness_to_alpha(roughness),
+    }
+  }
+
+  /// The BRDF for light arriving along `incoming` and leaving along `outgoing`, without the
+  /// cosine term.
+  pub fn evaluate(&self, normal: &Vector3, outgoing: &Vector3, incoming: &Vector3) -> Color {
+    let (n_dot_v, n_dot_l) = (normal.dot(outgoing), normal.dot(incoming));
+    if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+      return Color::default();
+    }
+    let half = (*outgoing + *incoming).normalize();
+    let fresnel = self.fresnel(outgoing.dot(&half));
+    // Light reflected by the specular layer never reaches the diffuse base beneath it.
+    let transmitted = Color {
+      red: 1.0 - fresnel.red,
+      green: 1.0 - fresnel.green,
+      blue: 1.0 - fresnel.blue,
+    };
+    let diffuse = self.diffuse * transmitted * (1.0 / PI) as f32;
+    diffuse + self.specular(normal, outgoing, incoming)
+  }
+
+  /// Picks a direction to continue a path in, choosing between the diffuse and specular
+  /// lobes by how much each reflects. The weight uses the density of both lobes combined,
+  /// so it stays bounded wherever either one is likely. Returns None if the viewer is below
+  /// the surface or the chosen direction points into it.
+  pub fn sample(&self, normal: &Vector3, outgoing: &Vector3, rng: &mut Rng) -> Option<BrdfSample> {
+    let n_dot_v = normal.dot(outgoing);
+    if n_dot_v <= 0.0 {
+      return None;
+    }
+    let specular = self.fresnel(n_dot_v).luminance();
+    let diffuse = self.diffuse.luminance() * (1.0 - specular);
+    let p_specular = if diffuse > 0.0 {
+      (specular / (specular + diffuse)).max(0.1) as f64
+    } else {
+      1.0
+    };
+
+    let direction = if rng.next_f64() < p_specular {
+      self.sample_reflection(normal, outgoing, rng)
+    } else {
+      cosine_hemisphere(normal, rng)
+    };
+    let n_dot_l = normal.dot(&direction);
+    if n_dot_l <= 0.0 {
+      return None;
+    }
+    let pdf = p_specular * self.specular_pdf(normal, outgoing, &direction) +
+              (1.0 - p_specular) * n_dot_l / PI;
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BrdfSample {
+      direction,
+      weight: self.evaluate(normal, outgoing, &direction) * (n_dot_l / pdf) as f32,
+    })
+  }
+
+  /// Like `sample`, but only from the specular lobe and weighted by it alone, for integrators
+  /// that account for diffuse reflection some other way.
+  pub fn sample_specular(&self,
+                         normal: &Vector3,
+                         outgoing: &Vector3,
+                         rng: &mut Rng)
+                         -> Option<BrdfSample> {
+    if normal.dot(outgoing) <= 0.0 {
+      return None;
+    }
+    let direction = self.sample_reflection(normal, outgoing, rng);
+    let n_dot_l = normal.dot(&direction);
+    if n_dot_l <= 0.0 {
+      return None;
+    }
+    let pdf = self.specular_pdf(normal, outgoing, &direction);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BrdfSample {
+      direction,
+      weight: self.specular(normal, outgoing, &direction) * (n_dot_l / pdf) as f32,
+    })
+  }
+
+  fn specular(&self, normal: &Vector3, outgoing: &Vector3, incoming: &Vector3) -> Color {
+    let (n_dot_v, n_dot_l) = (normal.dot(outgoing), normal.dot(incoming));
+    if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+      return Color::default();
+    }
+    let half = (*outgoing + *incoming).normalize();
+    let d = self.distribution(normal.dot(&half));
+    let g = self.smith_g1(n_dot_v) * self.smith_g1(n_dot_l);
+    self.fresnel(outgoing.dot(&half)) * (d * g / (4.0 * n_dot_v * n_dot_l)) as f32
+  }
+
+  /// The GGX normal distribution: the density of microfacets facing along a half vector
+  /// at `n_dot_h` to the normal.
+  fn distribution(&self, n_dot_h: f64) -> f64 {
+    if n_dot_h <= 0.0 {
+      return 0.0;
+    }
+    let a2 = self.alpha * self.alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+  }
+
+  /// Smith's masking function for GGX: the fraction of microfacets visible from a direction
+  /// at `n_dot_x` to the normal. Shadowing uses the same function towards the light.
+  fn smith_g1(&self, n_dot_x: f64) -> f64 {
+    let a2 = self.alpha * self.alpha;
+    2.0 * n_dot_x / (n_dot_x + (a2 + (1.0 - a2) * n_dot_x * n_dot_x).sqrt())
+  }
+
+  /// Schlick's approximation of the Fresnel reflectance, for light at `cos` to the
+  /// microfacet normal.
+  fn fresnel(&self, cos: f64) -> Color {
+    let weight = (1.0 - cos).clamp(0.0, 1.0).powi(5) as f32;
+    Color {
+      red: self.f0.red + (1.0 - self.f0.red) * weight,
+      green: self.f0.green + (1.0 - self.f0.green) * weight,
+      blue: self.f0.blue + (1.0 - self.f0.blue) * weight,
+    }
+  }
+
+  /// Reflects `outgoing` off a microfacet normal drawn by `sample_ggx_normal`.
+  fn sample_reflection(&self, normal: &Vector3, outgoing: &Vector3, rng: &mut Rng) -> Vector3 {
+    let half = sample_ggx_normal(normal, self.alpha, rng);
+    half * (2.0 * outgoing.dot(&half)) - *outgoing
+  }
+
+  /// The density with which `sample_reflection` picks `incoming`.
+  fn specular_pdf(&self, normal: &Vector3, outgoing: &Vector3, incoming: &Vector3) -> f64 {
+    let half = (*outgoing + *incoming).normalize();
+    let v_dot_h = outgoing.dot(&half);
+    if v_dot_h <= 0.0 {
+      return 0.0;
+    }
+    let n_dot_h = normal.dot(&half);
+    self.distribution(n_dot_h) * n_dot_h / (4.0 * v_dot_h)
+  }
+}
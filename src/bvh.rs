@@ -0,0 +1,253 @@
+use point::Point;
+use vector::Vector3;
+use rendering::{Hit, Ray};
+use std::cmp::Ordering;
+use std::f64;
+
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingBox {
+  pub min: Point,
+  pub max: Point,
+}
+impl BoundingBox {
+  pub fn empty() -> BoundingBox {
+    BoundingBox {
+      min: Point::from_one(f64::INFINITY),
+      max: Point::from_one(f64::NEG_INFINITY),
+    }
+  }
+
+  pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+    BoundingBox {
+      min: Point {
+        x: self.min.x.min(other.min.x),
+        y: self.min.y.min(other.min.y),
+        z: self.min.z.min(other.min.z),
+      },
+      max: Point {
+        x: self.max.x.max(other.max.x),
+        y: self.max.y.max(other.max.y),
+        z: self.max.z.max(other.max.z),
+      },
+    }
+  }
+
+  pub fn grow(&self, point: &Point) -> BoundingBox {
+    self.union(&BoundingBox {
+      min: *point,
+      max: *point,
+    })
+  }
+
+  pub fn centroid(&self) -> Point {
+    self.min + (self.max - self.min) * 0.5
+  }
+
+  pub fn extent(&self) -> Vector3 {
+    self.max - self.min
+  }
+
+  /// Returns the distance at which the ray enters the box, if it does so before `max_distance`.
+  pub fn intersect(&self, ray: &Ray, inv_direction: &Vector3, max_distance: f64) -> Option<f64> {
+    let mut t_min = 0.0;
+    let mut t_max = max_distance;
+
+    for axis in 0..3 {
+      let (origin, inv_d, lo, hi) = match axis {
+        0 => (ray.origin.x, inv_direction.x, self.min.x, self.max.x),
+        1 => (ray.origin.y, inv_direction.y, self.min.y, self.max.y),
+        _ => (ray.origin.z, inv_direction.z, self.min.z, self.max.z),
+      };
+      let mut t0 = (lo - origin) * inv_d;
+      let mut t1 = (hi - origin) * inv_d;
+      if inv_d < 0.0 {
+        ::std::mem::swap(&mut t0, &mut t1);
+      }
+      // NaN (0 * inf on a slab boundary) compares false and leaves the interval untouched.
+      if t0 > t_min {
+        t_min = t0;
+      }
+      if t1 < t_max {
+        t_max = t1;
+      }
+      if t_min > t_max {
+        return None;
+      }
+    }
+    Some(t_min)
+  }
+}
+
+fn axis_value(point: &Point, axis: usize) -> f64 {
+  match axis {
+    0 => point.x,
+    1 => point.y,
+    _ => point.z,
+  }
+}
+
+#[derive(Debug)]
+enum Node {
+  Leaf {
+    bounds: BoundingBox,
+    first: usize,
+    count: usize,
+  },
+  // The left child always directly follows its parent in `nodes`.
+  Interior {
+    bounds: BoundingBox,
+    right: usize,
+  },
+}
+
+/// A bounding volume hierarchy over a list of primitives, referenced by index.
+///
+/// Primitives without a bounding box (such as infinite planes) are kept aside and tested on
+/// every traversal.
+#[derive(Debug, Default)]
+pub struct Bvh {
+  nodes: Vec<Node>,
+  indices: Vec<usize>,
+  unbounded: Vec<usize>,
+}
+impl Bvh {
+  pub fn build(boxes: &[Option<BoundingBox>]) -> Bvh {
+    let mut bvh = Bvh::default();
+    let mut bounded = Vec::new();
+    for (i, bounds) in boxes.iter().enumerate() {
+      match *bounds {
+        Some(b) => bounded.push((i, b, b.centroid())),
+        None => bvh.unbounded.push(i),
+      }
+    }
+    if !bounded.is_empty() {
+      bvh.build_node(&mut bounded);
+    }
+    bvh
+  }
+
+  fn build_node(&mut self, prims: &mut [(usize, BoundingBox, Point)]) {
+    let bounds = prims.iter().fold(BoundingBox::empty(), |b, p| b.union(&p.1));
+
+    if prims.len() <= MAX_LEAF_SIZE {
+      self.nodes.push(Node::Leaf {
+        bounds,
+        first: self.indices.len(),
+        count: prims.len(),
+      });
+      self.indices.extend(prims.iter().map(|p| p.0));
+      return;
+    }
+
+    let centroid_bounds = prims.iter().fold(BoundingBox::empty(), |b, p| b.grow(&p.2));
+    let extent = centroid_bounds.extent();
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+      0
+    } else if extent.y >= extent.z {
+      1
+    } else {
+      2
+    };
+    prims.sort_by(|a, b| {
+      axis_value(&a.2, axis).partial_cmp(&axis_value(&b.2, axis)).unwrap_or(Ordering::Equal)
+    });
+
+    let node = self.nodes.len();
+    self.nodes.push(Node::Interior {
+      bounds,
+      right: 0,
+    });
+    let (left, right) = prims.split_at_mut(prims.len() / 2);
+    self.build_node(left);
+    let right_index = self.nodes.len();
+    self.build_node(right);
+    if let Node::Interior { ref mut right, .. } = self.nodes[node] {
+      *right = right_index;
+    }
+  }
+
+  /// The number of primitives the hierarchy was built over.
+  pub fn len(&self) -> usize {
+    self.indices.len() + self.unbounded.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Finds the closest primitive hit by `ray`. `intersect` is called with the index of each
+  /// candidate primitive and returns where the ray hits it, if it does.
+  pub fn closest<F>(&self, ray: &Ray, intersect: F) -> Option<(usize, Hit)>
+    where F: Fn(usize) -> Option<Hit>
+  {
+    let mut closest = None;
+    self.visit(ray, f64::INFINITY, |i, max_distance| match intersect(i) {
+      Some(hit) if hit.distance < max_distance => {
+        closest = Some((i, hit));
+        hit.distance
+      }
+      _ => max_distance,
+    });
+    closest
+  }
+
+  /// Returns true if `intersect` reports a hit for any primitive whose bounds the ray enters
+  /// before `max_distance`.
+  pub fn any<F>(&self, ray: &Ray, max_distance: f64, intersect: F) -> bool
+    where F: Fn(usize) -> bool
+  {
+    let mut found = false;
+    self.visit(ray, max_distance, |i, max_distance| if intersect(i) {
+      found = true;
+      -1.0
+    } else {
+      max_distance
+    });
+    found
+  }
+
+  /// Calls `visit_primitive` for every primitive whose bounds the ray enters before the current
+  /// maximum distance. The callback returns the new maximum; a negative value stops traversal.
+  fn visit<F>(&self, ray: &Ray, mut max_distance: f64, mut visit_primitive: F)
+    where F: FnMut(usize, f64) -> f64
+  {
+    for &i in &self.unbounded {
+      max_distance = visit_primitive(i, max_distance);
+      if max_distance < 0.0 {
+        return;
+      }
+    }
+    if self.nodes.is_empty() {
+      return;
+    }
+    let inv_direction = Vector3 {
+      x: ray.direction.x.recip(),
+      y: ray.direction.y.recip(),
+      z: ray.direction.z.recip(),
+    };
+
+    let mut stack = vec![0];
+    while let Some(n) = stack.pop() {
+      match self.nodes[n] {
+        Node::Leaf { ref bounds, first, count } => {
+          if bounds.intersect(ray, &inv_direction, max_distance).is_some() {
+            for &i in &self.indices[first..first + count] {
+              max_distance = visit_primitive(i, max_distance);
+              if max_distance < 0.0 {
+                return;
+              }
+            }
+          }
+        }
+        Node::Interior { ref bounds, right } => {
+          if bounds.intersect(ray, &inv_direction, max_distance).is_some() {
+            stack.push(right);
+            stack.push(n + 1);
+          }
+        }
+      }
+    }
+  }
+}
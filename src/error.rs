@@ -0,0 +1,42 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+  /// The scene file could not be read.
+  Io { path: PathBuf, error: io::Error },
+  /// The scene file could not be parsed. The message names the offending field, or the
+  /// texture or mesh file that failed to load.
+  Parse { path: PathBuf, message: String },
+  /// The scene file's extension is not one of .json, .yml or .yaml.
+  UnsupportedFormat { path: PathBuf },
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::Io { ref path, ref error } => {
+        write!(f, "unable to read scene file {}: {}", path.display(), error)
+      }
+      Error::Parse { ref path, ref message } => {
+        write!(f, "invalid scene file {}: {}", path.display(), message)
+      }
+      Error::UnsupportedFormat { ref path } => {
+        write!(f,
+               "unsupported scene file {}: must be .json, .yml or .yaml",
+               path.display())
+      }
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match *self {
+      Error::Io { ref error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
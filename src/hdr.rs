@@ -0,0 +1,131 @@
+//! Writers for floating-point images: Radiance RGBE (.hdr), Portable Float Map (.pfm) and
+//! uncompressed scanline OpenEXR (.exr). All of them store linear radiance, unclamped.
+
+use image::{ImageBuffer, Rgb};
+use image::hdr::HDREncoder;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A linear RGB image with one f32 per channel, as returned by `render_hdr`.
+pub type HdrImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HdrFormat {
+  Radiance,
+  Pfm,
+  OpenExr,
+}
+impl HdrFormat {
+  /// Picks a format from a file name's extension, or None if it isn't a floating-point format.
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Option<HdrFormat> {
+    let extension = path.as_ref().extension().and_then(|e| e.to_str())?.to_lowercase();
+    match extension.as_str() {
+      "hdr" => Some(HdrFormat::Radiance),
+      "pfm" => Some(HdrFormat::Pfm),
+      "exr" => Some(HdrFormat::OpenExr),
+      _ => None,
+    }
+  }
+
+  pub fn write<W: Write>(&self, writer: &mut W, image: &HdrImage) -> io::Result<()> {
+    match *self {
+      HdrFormat::Radiance => write_radiance(writer, image),
+      HdrFormat::Pfm => write_pfm(writer, image),
+      HdrFormat::OpenExr => write_exr(writer, image),
+    }
+  }
+
+  pub fn save<P: AsRef<Path>>(&self, path: P, image: &HdrImage) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    self.write(&mut writer, image)?;
+    writer.flush()
+  }
+}
+
+/// Writes a run-length encoded Radiance RGBE picture.
+pub fn write_radiance<W: Write>(writer: &mut W, image: &HdrImage) -> io::Result<()> {
+  let (width, height) = image.dimensions();
+  let pixels: Vec<Rgb<f32>> = image.pixels().cloned().collect();
+  HDREncoder::new(writer).encode(&pixels, width as usize, height as usize)
+}
+
+/// Writes a color Portable Float Map. Scanlines are stored bottom to top, little-endian.
+pub fn write_pfm<W: Write>(writer: &mut W, image: &HdrImage) -> io::Result<()> {
+  let (width, height) = image.dimensions();
+  write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+  for y in (0..height).rev() {
+    for x in 0..width {
+      for &channel in &image.get_pixel(x, y).data {
+        writer.write_all(&channel.to_le_bytes())?;
+      }
+    }
+  }
+  Ok(())
+}
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const EXR_PIXEL_FLOAT: i32 = 2;
+
+/// Writes a single-part, uncompressed scanline OpenEXR file with 32-bit float B, G and R
+/// channels (the format requires channels in alphabetical order).
+pub fn write_exr<W: Write>(writer: &mut W, image: &HdrImage) -> io::Result<()> {
+  let (width, height) = image.dimensions();
+  let mut header = Vec::new();
+  header.extend_from_slice(&EXR_MAGIC);
+  header.extend_from_slice(&2i32.to_le_bytes());
+
+  let mut channels = Vec::new();
+  for name in &["B", "G", "R"] {
+    channels.extend_from_slice(name.as_bytes());
+    channels.push(0);
+    channels.extend_from_slice(&EXR_PIXEL_FLOAT.to_le_bytes());
+    // pLinear and three reserved bytes, then x and y sampling.
+    channels.extend_from_slice(&[0, 0, 0, 0]);
+    channels.extend_from_slice(&1i32.to_le_bytes());
+    channels.extend_from_slice(&1i32.to_le_bytes());
+  }
+  channels.push(0);
+  exr_attribute(&mut header, "channels", "chlist", &channels);
+
+  exr_attribute(&mut header, "compression", "compression", &[0]);
+  let mut window = Vec::new();
+  for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+    window.extend_from_slice(&value.to_le_bytes());
+  }
+  exr_attribute(&mut header, "dataWindow", "box2i", &window);
+  exr_attribute(&mut header, "displayWindow", "box2i", &window);
+  exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+  exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+  exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+  exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+  header.push(0);
+  writer.write_all(&header)?;
+
+  // Every scanline is its own chunk: a y coordinate, a byte count, then each channel's row.
+  let row_bytes = width as u64 * 4 * 3;
+  let chunk_bytes = 8 + row_bytes;
+  let first_chunk = header.len() as u64 + 8 * height as u64;
+  for y in 0..height as u64 {
+    writer.write_all(&(first_chunk + y * chunk_bytes).to_le_bytes())?;
+  }
+  for y in 0..height {
+    writer.write_all(&(y as i32).to_le_bytes())?;
+    writer.write_all(&(row_bytes as i32).to_le_bytes())?;
+    for channel in (0..3).rev() {
+      for x in 0..width {
+        writer.write_all(&image.get_pixel(x, y).data[channel].to_le_bytes())?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+  header.extend_from_slice(name.as_bytes());
+  header.push(0);
+  header.extend_from_slice(kind.as_bytes());
+  header.push(0);
+  header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+  header.extend_from_slice(value);
+}
@@ -2,42 +2,170 @@
 extern crate serde_derive;
 extern crate image;
 extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
 
 pub mod scene;
 pub mod vector;
 pub mod point;
+pub mod matrix;
+pub mod quaternion;
+pub mod bvh;
+pub mod obj;
+pub mod error;
+pub mod hdr;
+pub mod transform;
+pub mod spectrum;
 mod rendering;
+mod path_tracing;
+mod sampling;
+mod brdf;
+mod noise;
 
-use scene::{Scene};
-use image::{DynamicImage, GenericImage, ImageBuffer, Rgba};
+pub use error::Error;
 
-use rendering::{Ray, cast_ray};
+use scene::{Scene, Color};
+use image::{DynamicImage, GenericImage, ImageBuffer, Rgb, Rgba};
+use hdr::HdrImage;
+use std::sync::Mutex;
+use std::thread;
+
+use rendering::{render_pixel, render_sample, BLACK};
 
 pub fn render(scene: &Scene) -> DynamicImage {
-  let mut image = DynamicImage::new_rgb8(scene.width, scene.height);
-  for x in 0..scene.width {
-    for y in 0..scene.height {
-      let ray = Ray::create_prime(x, y, scene);
-      image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
-      // match scene.trace(&ray) {
-      //   Option::Some(intersection) => image.put_pixel(x, y, get_color(scene, &ray, &intersection).to_rgba()),
-      //   Option::None => image.put_pixel(x, y, BLACK.to_rgba()),
-      // }
+  to_image(scene, &render_pixels(scene))
+}
+
+pub fn render_into(scene: &Scene, image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>) {
+  let pixels = render_pixels(scene);
+  for (i, color) in pixels.iter().enumerate() {
+    let (x, y) = (i as u32 % scene.width, i as u32 / scene.width);
+    image.put_pixel(x, y, encode_pixel(scene, *color, x, y));
+  }
+}
+
+/// Renders the scene to linear, unclamped radiance, for saving with one of the `hdr` writers.
+/// Exposure and tone mapping are left to whatever displays the image.
+pub fn render_hdr(scene: &Scene) -> HdrImage {
+  let pixels = render_pixels(scene);
+  ImageBuffer::from_fn(scene.width, scene.height, |x, y| {
+    let color = pixels[(y * scene.width + x) as usize];
+    Rgb { data: [color.red, color.green, color.blue] }
+  })
+}
+
+/// Returned by the callback of `render_progressive` to say whether rendering should go on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+  Continue,
+  Cancel,
+}
+
+/// Roughly how many times `render_progressive` reports progress, however few samples the
+/// render takes. Images with fewer rows than this report once a row.
+const PROGRESS_UPDATES: u32 = 16;
+
+/// Renders the scene one sample per pixel at a time, calling `callback` as it goes with the
+/// image so far and the fraction of the work done. Each pass is reported in bands of rows
+/// when there are too few passes to keep the callback busy otherwise, so that even a render
+/// with a single sample per pixel shows progress and can be cancelled midway. Returns None if
+/// the callback cancels the render; otherwise the final image is identical to what `render`
+/// produces.
+pub fn render_progressive<F>(scene: &Scene, mut callback: F) -> Option<DynamicImage>
+  where F: FnMut(&DynamicImage, f32) -> Progress
+{
+  let passes = scene.samples_per_pixel.max(1);
+  let band_height = scene.height.div_ceil(PROGRESS_UPDATES.div_ceil(passes)).max(1);
+  let bands = scene.height.div_ceil(band_height).max(1);
+  let width = scene.width as usize;
+  let mut accumulated = vec![(BLACK, 0.0); width * scene.height as usize];
+  let mut image = None;
+  for pass in 0..passes {
+    for band in 0..bands {
+      let first_row = band * band_height;
+      let last_row = (first_row + band_height).min(scene.height);
+      let rows = &mut accumulated[first_row as usize * width..last_row as usize * width];
+      for_each_row(scene, rows, |y, row| for (x, pixel) in row.iter_mut().enumerate() {
+        let (color, weight) = render_sample(scene, x as u32, first_row + y, pass);
+        pixel.0 = pixel.0 + color * weight;
+        pixel.1 += weight;
+      });
+
+      let pixels: Vec<Color> = accumulated.iter()
+        .map(|&(color, weight)| if weight > 0.0 {
+          color * weight.recip()
+        } else {
+          BLACK
+        })
+        .collect();
+      let partial = to_image(scene, &pixels);
+      let done = (pass * bands + band + 1) as f32 / (passes * bands) as f32;
+      if callback(&partial, done) == Progress::Cancel {
+        return None;
+      }
+      image = Some(partial);
     }
   }
   image
 }
 
-pub fn render_into(scene: &Scene, image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>) {
-  for y in 0..scene.height {
-    for x in 0..scene.width {
-      let ray = Ray::create_prime(x, y, scene);
-      image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
-      // match scene.trace(&ray) {
-      //   Option::Some(intersection) => image.put_pixel(x, y, get_color(scene, &ray, &intersection).to_rgba()),
-      //   Option::None => image.put_pixel(x, y, BLACK.to_rgba()),
-      // }
+fn to_image(scene: &Scene, pixels: &[Color]) -> DynamicImage {
+  let mut image = DynamicImage::new_rgb8(scene.width, scene.height);
+  for (i, color) in pixels.iter().enumerate() {
+    let (x, y) = (i as u32 % scene.width, i as u32 / scene.width);
+    image.put_pixel(x, y, encode_pixel(scene, *color, x, y));
+  }
+  image
+}
+
+/// Tone maps a rendered color and quantizes it for an 8-bit image.
+fn encode_pixel(scene: &Scene, color: Color, x: u32, y: u32) -> Rgba<u8> {
+  let color = scene.tone_map(color);
+  if scene.dither {
+    color.to_rgba_dithered(x, y)
+  } else {
+    color.to_rgba()
+  }
+}
+
+/// Renders every pixel of the scene in row-major order.
+fn render_pixels(scene: &Scene) -> Vec<Color> {
+  let mut pixels = vec![BLACK; scene.width as usize * scene.height as usize];
+  for_each_row(scene, &mut pixels, |y, row| for (x, pixel) in row.iter_mut().enumerate() {
+    *pixel = render_pixel(scene, x as u32, y);
+  });
+  pixels
+}
+
+/// Calls `render_row` for every row of `pixels`, spreading rows across `scene.threads`
+/// worker threads. Rows are handed out in any order, so each must be computed
+/// independently of the others.
+fn for_each_row<T, F>(scene: &Scene, pixels: &mut [T], render_row: F)
+  where T: Send,
+        F: Fn(u32, &mut [T]) + Sync
+{
+  let width = scene.width as usize;
+  if width == 0 {
+    return;
+  }
+
+  let height = pixels.len() / width;
+  let rows = Mutex::new(pixels.chunks_mut(width).enumerate());
+  let render_rows = || loop {
+    let next = rows.lock().unwrap().next();
+    match next {
+      Some((y, row)) => render_row(y as u32, row),
+      None => break,
     }
+  };
+
+  let threads = scene.threads.max(1).min(height);
+  if threads <= 1 {
+    render_rows();
+  } else {
+    thread::scope(|s| for _ in 0..threads {
+      s.spawn(render_rows);
+    });
   }
 }
 
@@ -54,26 +182,2194 @@ pub fn render_into(scene: &Scene, image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>)
 
 #[test]
 fn test_can_render_scene() {
+  use scene::{Camera, Element, Material, Sphere};
+  use point::Point;
+  use vector::Vector3;
+
+  let sphere = Element::Sphere(Sphere {
+    center: Point { x: 0.0, y: 0.0, z: -5.0 },
+    radius: 1.0,
+    transform: None,
+    material: Material { albedo: 0.18, ..diffuse_material(0.4, 1.0, 0.4) },
+  });
   let scene = Scene {
-    width: 800,
-    height: 600,
-    fov: 90.0,
-    sphere: Sphere {
-      center: Point {
-        x: 0.0,
-        y: 0.0,
-        z: -5.0,
-      },
-      radius: 1.0,
-      color: Color {
-        red: 0.4,
-        green: 1.0,
-        blue: 0.4,
-      },
+    width: 80,
+    height: 60,
+    camera: Camera {
+      position: Point::zero(),
+      look_at: Point { x: 0.0, y: 0.0, z: -1.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 90.0,
     },
+    ..test_scene(vec![sphere], vec![])
   };
 
   let img: DynamicImage = render(&scene);
   assert_eq!(scene.width, img.width());
   assert_eq!(scene.height, img.height());
 }
+
+#[test]
+fn test_can_render_portrait_scene() {
+  use scene::{Camera, DirectionalLight, Element, Light, Sphere};
+  use point::Point;
+  use vector::Vector3;
+
+  // A small sphere above and to the left of where the camera looks.
+  let sphere = Element::Sphere(Sphere {
+    center: Point { x: -1.0, y: 1.0, z: 0.0 },
+    radius: 0.5,
+    transform: None,
+    material: diffuse_material(1.0, 1.0, 1.0),
+  });
+  let light = Light::Directional(DirectionalLight {
+    direction: Vector3 { x: 0.0, y: -2.0, z: -3.0 }.normalize(),
+    color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+    intensity: 3.0,
+    spectrum: None,
+  });
+  let scene = Scene {
+    width: 30,
+    height: 60,
+    camera: Camera {
+      position: Point { x: 0.0, y: 2.0, z: 3.0 },
+      look_at: Point::zero(),
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 60.0,
+    },
+    ..test_scene(vec![sphere], vec![light])
+  };
+
+  let img = render(&scene).to_rgba();
+  assert_eq!(scene.width, img.width());
+  assert_eq!(scene.height, img.height());
+  // It shows up in the top left quarter of the image, and nowhere else.
+  let lit: Vec<(u32, u32)> = img.enumerate_pixels()
+    .filter(|&(_, _, pixel)| pixel.data[0] > 0)
+    .map(|(x, y, _)| (x, y))
+    .collect();
+  assert!(!lit.is_empty());
+  assert!(lit.iter().all(|&(x, y)| x < scene.width / 2 && y < scene.height / 2),
+          "{:?}",
+          lit);
+
+  // A camera that looks straight along its up vector has no way to tell left from right.
+  let camera = |look_at: &str| {
+    serde_yaml::from_str::<Scene>(&format!("
+width: 4
+height: 4
+max_recursion_depth: 1
+shadow_bias: 0.0001
+camera:
+  position: {{ x: 0, y: 0, z: 0 }}
+  look_at: {}
+  up: {{ x: 0, y: 1, z: 0 }}
+  fov: 90
+lights: []
+elements: []
+",
+                                           look_at))
+  };
+  assert!(camera("{ x: 0, y: 0, z: -1 }").is_ok());
+  for &(look_at, message) in &[("{ x: 0, y: 5, z: 0 }", "parallel"),
+                               ("{ x: 0, y: -1, z: 0 }", "parallel"),
+                               ("{ x: 0, y: 0, z: 0 }", "differ")] {
+    let error = camera(look_at).unwrap_err().to_string();
+    assert!(error.contains(message), "{}", error);
+  }
+}
+
+#[test]
+fn test_bvh_matches_linear_trace() {
+  use scene::{Element, Plane, Sphere};
+  use point::Point;
+  use vector::Vector3;
+  use rendering::{Intersectable, Ray};
+
+  let mut elements = vec![Element::Plane(Plane {
+    origin: Point { x: 0.0, y: -2.0, z: 0.0 },
+    normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+    transform: None,
+    material: diffuse_material(0.8, 0.5, 0.3),
+  })];
+  for i in 0..200 {
+    let f = i as f64;
+    elements.push(Element::Sphere(Sphere {
+      center: Point { x: (f * 0.7).sin() * 4.0, y: (f * 1.3).cos() * 2.0, z: -4.0 - f * 0.05 },
+      radius: 0.2 + (f * 0.37).sin().abs() * 0.3,
+      transform: None,
+      material: diffuse_material(0.8, 0.5, 0.3),
+    }));
+  }
+  let mut scene = test_scene(elements, vec![]);
+
+  // The scene builds its hierarchy by itself, and finds the same hits as testing every element.
+  let rays: Vec<Ray> = (0..2000)
+    .map(|i| {
+      let f = i as f64;
+      Ray {
+        origin: Point::zero(),
+        direction: Vector3 { x: (f * 0.013).sin() * 1.2, y: (f * 0.029).cos() * 0.8, z: -1.0 }
+          .normalize(),
+      }
+    })
+    .collect();
+  let linear = |scene: &Scene, ray: &Ray| {
+    scene.elements
+      .iter()
+      .enumerate()
+      .filter_map(|(i, e)| e.intersect(ray).map(|hit| (i, hit.distance)))
+      .min_by(|a, b| a.1.total_cmp(&b.1))
+  };
+  let traced = |scene: &Scene, ray: &Ray| {
+    scene.trace(ray).map(|hit| {
+      let index = scene.elements.iter().position(|e| ::std::ptr::eq(e, hit.element));
+      (index.unwrap(), hit.distance)
+    })
+  };
+  let mut hits = 0;
+  for ray in &rays {
+    let expected = linear(&scene, ray);
+    assert_eq!(expected, traced(&scene, ray));
+    assert_eq!(expected.is_some_and(|(_, d)| d < 10.0), scene.occluded(ray, 10.0));
+    hits += expected.is_some() as usize;
+  }
+  assert!(hits > 0 && hits < rays.len());
+
+  // Adding, replacing or moving elements rebuilds the hierarchy to match.
+  scene.elements.edit().push(Element::Sphere(Sphere {
+    center: Point { x: 0.0, y: 0.0, z: -1.0 },
+    radius: 0.5,
+    transform: None,
+    material: diffuse_material(1.0, 1.0, 1.0),
+  }));
+  let moved = Point { x: 3.0, y: 1.0, z: -6.0 };
+  if let Element::Sphere(ref mut sphere) = scene.elements.edit()[1] {
+    sphere.center = moved;
+  }
+  scene.elements.edit().swap(2, 3);
+  assert!(rays.iter().any(|ray| traced(&scene, ray).is_some_and(|(i, _)| i == 201)));
+  for ray in &rays {
+    assert_eq!(linear(&scene, ray), traced(&scene, ray));
+  }
+}
+
+#[test]
+fn test_threaded_render_matches_single_threaded() {
+  use scene::{Camera, Element, Light, Material, Plane, Sphere, SphericalLight, SurfaceType};
+  use point::Point;
+  use vector::Vector3;
+
+  let floor = Element::Plane(Plane {
+    origin: Point { x: 0.0, y: -1.0, z: 0.0 },
+    normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+    transform: None,
+    material: Material {
+      albedo: 0.3,
+      surface: SurfaceType::Reflective {
+        reflectivity: 0.3,
+        roughness: 0.0,
+      },
+      ..diffuse_material(0.5, 0.5, 0.5)
+    },
+  });
+  let glass = Element::Sphere(Sphere {
+    center: Point { x: 0.0, y: 0.0, z: -5.0 },
+    radius: 1.0,
+    transform: None,
+    material: Material {
+      albedo: 0.5,
+      surface: SurfaceType::Refractive {
+        index: 1.5,
+        transparency: 0.9,
+        roughness: 0.0,
+        absorption: None,
+        dispersion: None,
+      },
+      specular: Color { red: 0.3, green: 0.3, blue: 0.3 },
+      shininess: 50.0,
+      ..diffuse_material(0.9, 0.9, 1.0)
+    },
+  });
+  let light = Light::Spherical(SphericalLight {
+    position: Point { x: -2.0, y: 3.0, z: -2.0 },
+    color: Color { red: 1.0, green: 0.9, blue: 0.8 },
+    intensity: 300.0,
+    spectrum: None,
+    radius: 0.5,
+  });
+  let mut scene = Scene {
+    width: 64,
+    height: 48,
+    camera: Camera {
+      position: Point { x: 0.0, y: 1.0, z: 1.0 },
+      look_at: Point { x: 0.0, y: 0.0, z: -5.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 70.0,
+    },
+    max_recursion_depth: 6,
+    shadow_samples: 16,
+    ..test_scene(vec![floor, glass], vec![light])
+  };
+
+  let single = render(&scene).raw_pixels();
+  scene.threads = 7;
+  let threaded = render(&scene).raw_pixels();
+  assert!(single == threaded);
+}
+
+#[test]
+fn test_can_trace_obj_mesh() {
+  use scene::{Camera, Element, Material, Mesh, SurfaceType, Triangle};
+  use rendering::{Intersectable, Ray, TextureCoords};
+  use image::ImageFormat;
+  use point::Point;
+  use vector::Vector3;
+  use std::env;
+  use std::fs::{self, File};
+  use std::io::Write;
+
+  let obj = "# unit quad facing +z, split into two triangles\n\
+             v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             vn 0 0 1\n\
+             f 1/1/1 2/2/1 3/3/1 4/4/1\n";
+  let geometry = obj::parse(obj.as_bytes()).unwrap();
+  assert_eq!(2, geometry.faces.len());
+
+  let material = || Material { albedo: 0.5, ..diffuse_material(1.0, 1.0, 1.0) };
+  let quad = Element::Mesh(Mesh {
+    geometry,
+    transform: None,
+    material: material(),
+  });
+  let triangle = Element::Triangle(Triangle {
+    vertices: [Point { x: 2.0, y: 0.0, z: -3.0 },
+               Point { x: 4.0, y: 0.0, z: -3.0 },
+               Point { x: 3.0, y: 2.0, z: -3.0 }],
+    normals: None,
+    texture_coords: None,
+    transform: None,
+    material: material(),
+  });
+  let scene = Scene {
+    width: 8,
+    height: 8,
+    camera: Camera {
+      position: Point::zero(),
+      look_at: Point { x: 0.0, y: 0.0, z: -1.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 90.0,
+    },
+    max_recursion_depth: 4,
+    ..test_scene(vec![quad, triangle], vec![])
+  };
+
+  let ray = Ray {
+    origin: Point { x: 0.5, y: 0.25, z: 5.0 },
+    direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+  };
+  let intersection = scene.trace(&ray).unwrap();
+  assert!((intersection.distance - 5.0).abs() < 1e-9);
+  let hit_point = Point { x: 0.5, y: 0.25, z: 0.0 };
+  let normal = intersection.element.surface_normal(&hit_point, intersection.face);
+  assert!((normal.z - 1.0).abs() < 1e-9);
+  let coords = intersection.element.texture_coords(&hit_point, intersection.face);
+  assert!((coords.x - 0.75).abs() < 1e-6);
+  assert!((coords.y - 0.375).abs() < 1e-6);
+
+  let ray = Ray {
+    origin: Point { x: 3.0, y: 0.5, z: 0.0 },
+    direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+  };
+  assert!((scene.trace(&ray).unwrap().distance - 3.0).abs() < 1e-9);
+
+  // Seen from behind, the quad is shaded from behind, unless it refracts.
+  let ray = Ray {
+    origin: Point { x: 0.5, y: 0.25, z: -5.0 },
+    direction: Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+  };
+  let intersection = scene.trace(&ray).unwrap();
+  let normal = rendering::facing_normal(&intersection, hit_point, ray.direction);
+  assert!((normal.z + 1.0).abs() < 1e-9);
+  let glass = Element::Triangle(Triangle {
+    vertices: [Point { x: -2.0, y: -2.0, z: 0.0 },
+               Point { x: 2.0, y: -2.0, z: 0.0 },
+               Point { x: 0.0, y: 2.0, z: 0.0 }],
+    normals: None,
+    texture_coords: None,
+    transform: None,
+    material: Material {
+      surface: SurfaceType::Refractive {
+        index: 1.5,
+        transparency: 1.0,
+        roughness: 0.0,
+        absorption: None,
+        dispersion: None,
+      },
+      ..material()
+    },
+  });
+  let intersection = scene::Intersection::new(glass.intersect(&ray).unwrap(), &glass).unwrap();
+  let normal = rendering::facing_normal(&intersection, hit_point, ray.direction);
+  assert!((normal.z - 1.0).abs() < 1e-9);
+
+  // Mesh and texture paths are relative to the scene file, wherever it's loaded from.
+  let directory = env::temp_dir().join("raytracer_test_can_trace_obj_mesh");
+  fs::create_dir_all(&directory).unwrap();
+  File::create(directory.join("quad.obj")).unwrap().write_all(obj.as_bytes()).unwrap();
+  let red = ImageBuffer::from_pixel(1, 1, Rgba { data: [255, 0, 0, 255] });
+  let red = DynamicImage::ImageRgba8(red);
+  red.save(&mut File::create(directory.join("red.png")).unwrap(), ImageFormat::PNG).unwrap();
+  let scene_path = directory.join("scene.yml");
+  File::create(&scene_path)
+    .unwrap()
+    .write_all(b"
+width: 4
+height: 4
+max_recursion_depth: 1
+shadow_bias: 0.0001
+camera:
+  position: { x: 0, y: 0, z: 5 }
+  look_at: { x: 0, y: 0, z: 0 }
+  up: { x: 0, y: 1, z: 0 }
+  fov: 90
+lights: []
+elements:
+  - Mesh:
+      obj: quad.obj
+      material:
+        coloration:
+          Texture: red.png
+        albedo: 0.5
+        surface: Diffuse
+")
+    .unwrap();
+  let scene = Scene::load(&scene_path).unwrap();
+  let hit = scene.trace(&ray).unwrap();
+  assert!((hit.distance - 5.0).abs() < 1e-9);
+  let coords = TextureCoords { x: 0.5, y: 0.5 };
+  let color = hit.element.material().coloration.color(&coords, &Point::zero(), 0.0);
+  assert_eq!((1.0, 0.0), (color.red, color.green));
+}
+
+#[test]
+fn test_supersampling_smooths_edges() {
+  use scene::{Camera, DirectionalLight, Element, Filter, Light, Material, SamplePattern, Sphere};
+  use point::Point;
+  use vector::Vector3;
+  use std::collections::HashSet;
+
+  let sphere = Element::Sphere(Sphere {
+    center: Point { x: 0.0, y: 0.0, z: -3.0 },
+    radius: 1.5,
+    transform: None,
+    material: Material { albedo: 1.0, ..diffuse_material(1.0, 1.0, 1.0) },
+  });
+  let light = Light::Directional(DirectionalLight {
+    direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+    color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+    intensity: 3.0,
+    spectrum: None,
+  });
+  let mut scene = Scene {
+    width: 32,
+    height: 32,
+    camera: Camera {
+      position: Point::zero(),
+      look_at: Point { x: 0.0, y: 0.0, z: -1.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 90.0,
+    },
+    max_recursion_depth: 4,
+    sample_pattern: SamplePattern::Stratified,
+    filter: Filter::Box,
+    ..test_scene(vec![sphere], vec![light])
+  };
+
+  // Blending the sphere's silhouette into the background introduces new shades.
+  let levels = |scene: &Scene| render(scene).raw_pixels().into_iter().collect::<HashSet<_>>().len();
+  let aliased = levels(&scene);
+
+  scene.samples_per_pixel = 16;
+  scene.sample_pattern = SamplePattern::Jittered;
+  scene.filter = Filter::Tent;
+  assert!(levels(&scene) > aliased);
+}
+
+#[test]
+fn test_samples_cover_the_pixel() {
+  use scene::SamplePattern;
+  use rendering::sample_offset;
+  use sampling::{Rng, permute};
+
+  for count in 1..40 {
+    for &seed in &[0, 1, 0xdead_beef] {
+      let mut shuffled: Vec<u32> = (0..count).map(|i| permute(i, count, seed)).collect();
+      shuffled.sort();
+      assert_eq!((0..count).collect::<Vec<_>>(), shuffled);
+    }
+  }
+
+  // Three samples can't fill a 2x2 grid, but they can take a third of the pixel each way.
+  let mut scene = test_scene(vec![], vec![]);
+  scene.samples_per_pixel = 3;
+  for &pattern in &[SamplePattern::Stratified, SamplePattern::Jittered] {
+    scene.sample_pattern = pattern;
+    let mut quadrants = [0; 4];
+    for (x, y) in (0..8).flat_map(|x| (0..8).map(move |y| (x, y))) {
+      let offsets: Vec<(f64, f64)> = (0..3)
+        .map(|i| sample_offset(&scene, x, y, i, &mut Rng::for_sample(x, y, i)))
+        .collect();
+      let thirds = |axis: fn(&(f64, f64)) -> f64| {
+        let mut thirds: Vec<u32> = offsets.iter().map(|o| ((axis(o) + 0.5) * 3.0) as u32).collect();
+        thirds.sort();
+        thirds
+      };
+      assert_eq!(vec![0, 1, 2], thirds(|o| o.0));
+      assert_eq!(vec![0, 1, 2], thirds(|o| o.1));
+      for &(dx, dy) in &offsets {
+        quadrants[(dx >= 0.0) as usize + 2 * (dy >= 0.0) as usize] += 1;
+      }
+    }
+    // Jittering reshuffles the rows for every pixel, so every quadrant gets its share.
+    if let SamplePattern::Jittered = pattern {
+      assert!(quadrants.iter().all(|&n| n > 8 * 8 * 3 / 8), "{:?}", quadrants);
+    }
+  }
+}
+
+#[test]
+fn test_load_reports_errors() {
+  use std::env;
+  use std::fs::File;
+  use std::io::Write;
+
+  match Scene::load("does/not/exist.yml") {
+    Err(Error::Io { .. }) => {}
+    other => panic!("expected an I/O error, got {:?}", other.err()),
+  }
+  match Scene::load("scene.txt") {
+    Err(Error::UnsupportedFormat { .. }) => {}
+    other => panic!("expected an unsupported format error, got {:?}", other.err()),
+  }
+
+  let path = env::temp_dir().join("raytracer_test_load_reports_errors.json");
+  File::create(&path)
+    .unwrap()
+    .write_all(br#"{
+      "width": 4, "height": 4, "max_recursion_depth": 1, "shadow_bias": 0.0001,
+      "camera": {
+        "position": { "x": 0, "y": 0, "z": 0 },
+        "look_at": { "x": 0, "y": 0, "z": -1 },
+        "up": { "x": 0, "y": 1, "z": 0 },
+        "fov": 90
+      },
+      "lights": [],
+      "elements": [{ "Sphere": {
+        "center": { "x": 0, "y": 0, "z": -3 }, "radius": 1,
+        "material": {
+          "coloration": { "Texture": "no/such/texture.png" },
+          "albedo": 0.5, "surface": "Diffuse"
+        }
+      }}]
+    }"#)
+    .unwrap();
+  match Scene::load(&path) {
+    Err(Error::Parse { ref message, .. }) => assert!(message.contains("no/such/texture.png")),
+    other => panic!("expected a parse error, got {:?}", other.err()),
+  }
+}
+
+#[test]
+fn test_box_faces() {
+  use scene::Element;
+  use rendering::{Intersectable, Ray};
+  use point::Point;
+  use vector::Vector3;
+
+  let element: Element = serde_json::from_str(r#"{ "Box": {
+    "min": { "x": -1, "y": -1, "z": -1 },
+    "max": { "x": 1, "y": 1, "z": 1 },
+    "rotation": { "x": 0, "y": 45, "z": 0 },
+    "material": {
+      "coloration": { "Color": { "red": 1, "green": 1, "blue": 1 } },
+      "albedo": 0.5, "surface": "Diffuse"
+    }
+  }}"#)
+    .unwrap();
+
+  // Looking down -z at a box turned 45 degrees about y hits its leading edge.
+  let ray = Ray {
+    origin: Point { x: 0.0, y: 0.0, z: 5.0 },
+    direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+  };
+  let hit = element.intersect(&ray).unwrap();
+  assert!((hit.distance - (5.0 - 2f64.sqrt())).abs() < 1e-9);
+
+  let ray = Ray {
+    origin: Point { x: 0.5, y: 0.0, z: 5.0 },
+    direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+  };
+  let hit = element.intersect(&ray).unwrap();
+  let hit_point = ray.origin + ray.direction * hit.distance;
+  let normal = element.surface_normal(&hit_point, hit.face);
+  let expected = 0.5f64.sqrt();
+  assert!((normal.x - expected).abs() < 1e-9 && (normal.z - expected).abs() < 1e-9);
+  let coords = element.texture_coords(&hit_point, hit.face);
+  assert!((coords.y - 0.5).abs() < 1e-6);
+
+  // From inside, the ray leaves through the far face.
+  let ray = Ray {
+    origin: Point::zero(),
+    direction: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+  };
+  let hit = element.intersect(&ray).unwrap();
+  assert!((hit.distance - 1.0).abs() < 1e-9);
+  assert!((element.surface_normal(&Point::zero(), hit.face).y - 1.0).abs() < 1e-9);
+
+  // A box turns about its own center, before its transform places it.
+  let element: Element = serde_json::from_str(r#"{ "Box": {
+    "min": { "x": 1, "y": -1, "z": -1 },
+    "max": { "x": 3, "y": 1, "z": 1 },
+    "rotation": { "x": 0, "y": 45, "z": 0 },
+    "transform": { "translate": { "x": 0, "y": 2, "z": 0 } },
+    "material": {
+      "coloration": { "Color": { "red": 1, "green": 1, "blue": 1 } },
+      "albedo": 0.5, "surface": "Diffuse"
+    }
+  }}"#)
+    .unwrap();
+  let ray = Ray {
+    origin: Point { x: 2.0, y: 2.0, z: 5.0 },
+    direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+  };
+  let hit = element.intersect(&ray).unwrap();
+  assert!((hit.distance - (5.0 - 2f64.sqrt())).abs() < 1e-9);
+}
+
+#[test]
+fn test_box_room_is_lit_from_inside() {
+  use scene::{Camera, Cuboid, Element, Integrator, Light, SphericalLight};
+  use point::Point;
+  use vector::Vector3;
+
+  // A camera and a light inside a closed box see its walls lit, as in a room.
+  let room = Element::Box(Cuboid {
+    min: Point::from_one(-5.0),
+    max: Point::from_one(5.0),
+    transform: None,
+    material: diffuse_material(0.8, 0.8, 0.8),
+  });
+  let light = Light::Spherical(SphericalLight {
+    position: Point { x: 0.0, y: 3.0, z: 0.0 },
+    color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+    intensity: 500.0,
+    spectrum: None,
+    radius: 0.0,
+  });
+  let mut scene = Scene {
+    camera: Camera {
+      position: Point { x: 0.0, y: -2.0, z: 3.0 },
+      look_at: Point { x: 0.0, y: 0.0, z: -5.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 90.0,
+    },
+    ..test_scene(vec![room], vec![light])
+  };
+  for &integrator in &[Integrator::Whitted, Integrator::PathTracing] {
+    scene.integrator = integrator;
+    let image = render(&scene).to_rgb();
+    assert!(image.pixels().all(|pixel| pixel.data[0] > 0), "{:?}", integrator);
+  }
+}
+
+#[test]
+fn test_area_lights_match_point_lights_from_afar() {
+  use scene::{Color, DiskLight, Light, SphericalLight};
+  use sampling::Rng;
+  use point::Point;
+  use vector::Vector3;
+
+  let white = Color { red: 1.0, green: 1.0, blue: 1.0 };
+  let hit_point = Point { x: 0.0, y: -100.0, z: 0.0 };
+  let point = Light::Spherical(SphericalLight {
+    position: Point::zero(),
+    color: white,
+    intensity: 1000.0,
+    spectrum: None,
+    radius: 0.0,
+  });
+  let sphere = Light::Spherical(SphericalLight {
+    position: Point::zero(),
+    color: white,
+    intensity: 1000.0,
+    spectrum: None,
+    radius: 1.0,
+  });
+  let disk = Light::Disk(DiskLight {
+    position: Point::zero(),
+    normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+    radius: 1.0,
+    color: white,
+    intensity: 1000.0,
+    spectrum: None,
+  });
+  assert!(point.is_point() && !sphere.is_point() && !disk.is_point());
+
+  let expected = point.sample(&hit_point, (0.5, 0.5)).intensity;
+  let mut rng = Rng::new(1);
+  // A one-sided Lambertian disk sends 4x more power straight ahead than an isotropic sphere.
+  for &(ref light, scale) in &[(sphere, 1.0), (disk, 4.0)] {
+    let mean = (0..256)
+      .map(|_| light.sample(&hit_point, (rng.next_f64(), rng.next_f64())).intensity)
+      .sum::<f32>() / 256.0;
+    assert!((mean / (expected * scale) - 1.0).abs() < 0.01);
+  }
+}
+
+#[test]
+fn test_rectangular_light_casts_soft_shadows() {
+  use scene::{Camera, Cuboid, Element, Light, Plane, RectangularLight, SphericalLight};
+  use rendering::render_pixel;
+  use point::Point;
+  use vector::Vector3;
+
+  // A 2x2 light at a height of 4, emitting downwards, with a 2x2 plate halfway to the floor.
+  let white = Color { red: 1.0, green: 1.0, blue: 1.0 };
+  let rectangle = Light::Rectangular(RectangularLight {
+    position: Point { x: 0.0, y: 4.0, z: -6.0 },
+    u: Vector3 { x: 2.0, y: 0.0, z: 0.0 },
+    v: Vector3 { x: 0.0, y: 0.0, z: 2.0 },
+    color: white,
+    intensity: 100.0,
+    spectrum: None,
+  });
+  let corner = rectangle.sample(&Point { x: -1.0, y: 0.0, z: -7.0 }, (0.0, 0.0));
+  assert!((corner.distance - 4.0).abs() < 1e-9);
+  assert!((corner.direction.y - 1.0).abs() < 1e-9);
+
+  let elements = vec![Element::Plane(Plane {
+                        origin: Point::zero(),
+                        normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+                        transform: None,
+                        material: diffuse_material(1.0, 1.0, 1.0),
+                      }),
+                      Element::Box(Cuboid {
+                        min: Point { x: -1.0, y: 1.95, z: -7.0 },
+                        max: Point { x: 1.0, y: 2.05, z: -5.0 },
+                        transform: None,
+                        material: diffuse_material(1.0, 1.0, 1.0),
+                      })];
+  let mut scene = test_scene(elements, vec![rectangle]);
+  // Looking straight down from a height of 10, pixel column i sees the floor at
+  // x = (i + 0.5) / 2 - 10 (or its mirror image, which is lit the same way).
+  scene.width = 40;
+  scene.height = 40;
+  scene.camera = Camera {
+    position: Point { x: 0.0, y: 10.0, z: -6.0 },
+    look_at: Point { x: 0.0, y: 0.0, z: -6.0 },
+    up: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+    fov: 90.0,
+  };
+  scene.shadow_samples = 64;
+  let floor = |scene: &Scene, x: f64| render_pixel(scene, ((x + 10.0) * 2.0) as u32, 20).red;
+
+  let shadowed: Vec<f32> = [1.75, 2.25, 3.25].iter().map(|&x| floor(&scene, x)).collect();
+  let plate = scene.elements.edit().pop().unwrap();
+  let visible: Vec<f32> =
+    [1.75, 2.25, 3.25].iter().zip(&shadowed).map(|(&x, &s)| s / floor(&scene, x)).collect();
+
+  // Within x = 1 the plate hides all of the light, and past x = 3 none of it. In between
+  // it hides a part that shrinks further out.
+  assert!(0.2 < visible[0] && visible[0] < visible[1] && visible[1] < 0.8,
+          "{:?}",
+          visible);
+  assert_eq!(1.0, visible[2]);
+
+  // A point light at the same place casts a hard shadow, with its edge at x = 2.
+  scene.elements.edit().push(plate);
+  scene.lights = vec![Light::Spherical(SphericalLight {
+                        position: Point { x: 0.0, y: 4.0, z: -6.0 },
+                        color: white,
+                        intensity: 100.0,
+                        spectrum: None,
+                        radius: 0.0,
+                      })];
+  assert_eq!(0.0, floor(&scene, 1.75));
+  assert!(floor(&scene, 2.25) > 0.0);
+}
+
+#[test]
+fn test_progressive_render_converges_to_render() {
+  use scene::{Camera, DiskLight, Element, Filter, Light, SamplePattern, Sphere};
+  use point::Point;
+  use vector::Vector3;
+
+  let sphere = Element::Sphere(Sphere {
+    center: Point { x: 0.0, y: 0.0, z: -3.0 },
+    radius: 1.0,
+    transform: None,
+    material: diffuse_material(0.8, 0.2, 0.2),
+  });
+  let light = Light::Disk(DiskLight {
+    position: Point { x: 0.0, y: 3.0, z: -1.0 },
+    normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+    radius: 1.0,
+    color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+    intensity: 200.0,
+    spectrum: None,
+  });
+  let scene = Scene {
+    width: 24,
+    height: 16,
+    camera: Camera {
+      position: Point::zero(),
+      look_at: Point { x: 0.0, y: 0.0, z: -1.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 90.0,
+    },
+    max_recursion_depth: 4,
+    samples_per_pixel: 4,
+    sample_pattern: SamplePattern::Jittered,
+    filter: Filter::Gaussian,
+    threads: 3,
+    ..test_scene(vec![sphere], vec![light])
+  };
+
+  let mut progress = vec![];
+  let image = render_progressive(&scene, |partial, fraction| {
+      assert_eq!(scene.width, partial.width());
+      progress.push(fraction);
+      Progress::Continue
+    })
+    .unwrap();
+  assert_eq!((1..17).map(|i| i as f32 / 16.0).collect::<Vec<_>>(), progress);
+  assert!(image.raw_pixels() == render(&scene).raw_pixels());
+
+  let mut updates = 0;
+  let cancelled = render_progressive(&scene, |_, _| {
+    updates += 1;
+    Progress::Cancel
+  });
+  assert!(cancelled.is_none());
+  assert_eq!(1, updates);
+
+  // With a single sample per pixel, the image fills in a row at a time, and the render
+  // stops as soon as it is cancelled.
+  let mut scene = scene;
+  scene.samples_per_pixel = 1;
+  let complete = render(&scene).to_rgb();
+  let mut updates = vec![];
+  let cancelled = render_progressive(&scene, |partial, fraction| {
+    let partial = partial.to_rgb();
+    let done = updates.len() as u32 + 1;
+    for y in 0..scene.height {
+      for x in 0..scene.width {
+        let expected = if y < done { complete.get_pixel(x, y).data } else { [0, 0, 0] };
+        assert_eq!(expected, partial.get_pixel(x, y).data);
+      }
+    }
+    updates.push(fraction);
+    if done < 8 {
+      Progress::Continue
+    } else {
+      Progress::Cancel
+    }
+  });
+  assert!(cancelled.is_none());
+  assert_eq!((1..9).map(|i| i as f32 / 16.0).collect::<Vec<_>>(), updates);
+}
+
+#[cfg(test)]
+fn test_scene(elements: Vec<scene::Element>, lights: Vec<scene::Light>) -> Scene {
+  use scene::{Camera, Elements};
+  use point::Point;
+  use vector::Vector3;
+
+  Scene {
+    width: 32,
+    height: 24,
+    camera: Camera {
+      position: Point { x: 0.0, y: 1.0, z: 2.0 },
+      look_at: Point { x: 0.0, y: 0.0, z: -3.0 },
+      up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      fov: 75.0,
+    },
+    max_recursion_depth: 8,
+    shadow_bias: 1e-6,
+    elements: Elements::new(elements),
+    lights,
+    integrator: Default::default(),
+    spectral: false,
+    shadow_samples: 4,
+    samples_per_pixel: 1,
+    glossy_samples: 8,
+    sample_pattern: Default::default(),
+    filter: Default::default(),
+    tone_mapper: Default::default(),
+    exposure: 0.0,
+    dither: false,
+    threads: 1,
+  }
+}
+
+#[cfg(test)]
+fn diffuse_material(red: f32, green: f32, blue: f32) -> scene::Material {
+  use scene::{Coloration, Material, SurfaceType};
+
+  Material {
+    coloration: Coloration::Color(Color { red, green, blue }),
+    albedo: 0.8,
+    surface: SurfaceType::Diffuse,
+    specular: Default::default(),
+    shininess: 32.0,
+    normal_map: None,
+    bump_map: None,
+  }
+}
+
+/// A `test_scene` with a white wall across z = -6, lit by a point light above it, behind
+/// `elements`.
+#[cfg(test)]
+fn lit_wall_scene(mut elements: Vec<scene::Element>) -> Scene {
+  use scene::{Element, Light, Plane, SphericalLight};
+  use point::Point;
+  use vector::Vector3;
+
+  elements.insert(0,
+                  Element::Plane(Plane {
+                    origin: Point { x: 0.0, y: 0.0, z: -6.0 },
+                    normal: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+                    transform: None,
+                    material: diffuse_material(1.0, 1.0, 1.0),
+                  }));
+  let light = Light::Spherical(SphericalLight {
+    position: Point { x: 0.0, y: 5.0, z: -5.0 },
+    color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+    intensity: 100.0,
+    spectrum: None,
+    radius: 0.0,
+  });
+  test_scene(elements, vec![light])
+}
+
+#[test]
+fn test_path_tracing_adds_indirect_light() {
+  use scene::{Element, Integrator, Light, Plane, Sphere, SphericalLight};
+  use point::Point;
+  use vector::Vector3;
+
+  let mut scene = test_scene(vec![Element::Plane(Plane {
+                                    origin: Point { x: 0.0, y: -1.0, z: 0.0 },
+                                    normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+                                    transform: None,
+                                    material: diffuse_material(0.9, 0.9, 0.9),
+                                  }),
+                                  Element::Plane(Plane {
+                                    origin: Point { x: 0.0, y: 0.0, z: -6.0 },
+                                    normal: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+                                    transform: None,
+                                    material: diffuse_material(0.9, 0.2, 0.2),
+                                  }),
+                                  Element::Sphere(Sphere {
+                                    center: Point { x: 0.0, y: 0.0, z: -3.0 },
+                                    radius: 1.0,
+                                    transform: None,
+                                    material: diffuse_material(0.2, 0.9, 0.2),
+                                  })],
+                             vec![Light::Spherical(SphericalLight {
+                                    position: Point { x: 1.0, y: 3.0, z: -1.0 },
+                                    color: rendering::WHITE,
+                                    intensity: 100.0,
+                                    spectrum: None,
+                                    radius: 0.0,
+                                  })]);
+  scene.samples_per_pixel = 16;
+  let brightness =
+    |scene: &Scene| render(scene).raw_pixels().iter().map(|&p| p as u64).sum::<u64>();
+  let whitted = brightness(&scene);
+
+  scene.integrator = Integrator::PathTracing;
+  let path_traced = render(&scene).raw_pixels();
+  assert!(brightness(&scene) > whitted);
+
+  scene.threads = 4;
+  assert!(render(&scene).raw_pixels() == path_traced);
+}
+
+#[test]
+fn test_path_tracing_converges_in_a_furnace() {
+  use scene::{Element, Integrator, Light, Material, SphericalLight, Triangle};
+  use rendering::render_pixel;
+  use point::Point;
+  use vector::Vector3;
+  use std::f64::consts::PI;
+
+  // Inside a closed sphere with a light at its center, every wall point gets the same direct
+  // irradiance E, and sees walls as bright as itself. Each bounce gives back a share of the
+  // light equal to the albedo, so path tracing should reach 1 / (1 - albedo) times the
+  // direct light alone: the sum of the albedo's powers.
+  let on_sphere = |row: u32, column: u32| {
+    let (theta, phi) = (PI * row as f64 / 32.0, PI * column as f64 / 32.0);
+    Vector3 { x: theta.sin() * phi.cos(), y: theta.cos(), z: theta.sin() * phi.sin() }
+  };
+  for &albedo in &[0.5f32, 0.8] {
+    let mut elements = vec![];
+    for row in 0..32 {
+      for column in 0..64 {
+        let corners = [on_sphere(row, column),
+                       on_sphere(row + 1, column),
+                       on_sphere(row + 1, column + 1),
+                       on_sphere(row, column + 1)];
+        for &(a, b, c) in &[(0, 1, 2), (0, 2, 3)] {
+          elements.push(Element::Triangle(Triangle {
+            vertices: [Point::zero() + corners[a] * 5.0,
+                       Point::zero() + corners[b] * 5.0,
+                       Point::zero() + corners[c] * 5.0],
+            normals: Some([-corners[a], -corners[b], -corners[c]]),
+            texture_coords: None,
+            transform: None,
+            material: Material { albedo, ..diffuse_material(1.0, 1.0, 1.0) },
+          }));
+        }
+      }
+    }
+    let light = Light::Spherical(SphericalLight {
+      position: Point::zero(),
+      color: rendering::WHITE,
+      intensity: 1000.0,
+      spectrum: None,
+      radius: 0.0,
+    });
+    let mut scene = test_scene(elements, vec![light]);
+    scene.camera.position = Point::zero();
+    scene.max_recursion_depth = 50;
+    scene.samples_per_pixel = 1024;
+    let mean = |scene: &Scene| {
+      let pixels = [(4, 4), (16, 12), (28, 20)];
+      pixels.iter().map(|&(x, y)| render_pixel(scene, x, y).red as f64).sum::<f64>() / 3.0
+    };
+
+    let direct = mean(&scene);
+    scene.integrator = Integrator::PathTracing;
+    let ratio = mean(&scene) / direct;
+    assert!((ratio * (1.0 - albedo as f64) - 1.0).abs() < 0.03, "{} {}", albedo, ratio);
+  }
+}
+
+#[test]
+fn test_hdr_render_keeps_dynamic_range() {
+  use scene::{Element, Light, Sphere, SphericalLight};
+  use hdr::HdrFormat;
+  use image::hdr::HDRDecoder;
+  use point::Point;
+
+  let scene = test_scene(vec![Element::Sphere(Sphere {
+                                center: Point { x: 0.0, y: 0.0, z: -3.0 },
+                                radius: 1.0,
+                                transform: None,
+                                material: diffuse_material(0.9, 0.9, 0.9),
+                              })],
+                         vec![Light::Spherical(SphericalLight {
+                                position: Point { x: 0.0, y: 2.0, z: 0.0 },
+                                color: rendering::WHITE,
+                                intensity: 2000.0,
+                                spectrum: None,
+                                radius: 0.0,
+                              })]);
+  let hdr = render_hdr(&scene);
+  assert_eq!(hdr.dimensions(), (scene.width, scene.height));
+  assert!(hdr.pixels().any(|p| p.data[0] > 1.0));
+
+  // The 8-bit render is the same radiance, clipped and encoded.
+  let ldr = render(&scene).to_rgba();
+  for (x, y, pixel) in hdr.enumerate_pixels() {
+    let color = Color { red: pixel.data[0], green: pixel.data[1], blue: pixel.data[2] };
+    assert_eq!(color.to_rgba(), *ldr.get_pixel(x, y));
+  }
+
+  let mut radiance = Vec::new();
+  HdrFormat::Radiance.write(&mut radiance, &hdr).unwrap();
+  let decoded = HDRDecoder::new(&radiance[..]).unwrap().read_image_hdr().unwrap();
+  for (original, decoded) in hdr.pixels().zip(decoded.iter()) {
+    for c in 0..3 {
+      assert!((original.data[c] - decoded.data[c]).abs() <= original.data[c] / 64.0 + 1e-6);
+    }
+  }
+
+  let pixel_bytes = (scene.width * scene.height * 12) as usize;
+  let mut pfm = Vec::new();
+  HdrFormat::Pfm.write(&mut pfm, &hdr).unwrap();
+  let header = format!("PF\n{} {}\n-1.0\n", scene.width, scene.height);
+  assert!(pfm.starts_with(header.as_bytes()));
+  assert_eq!(pfm.len(), header.len() + pixel_bytes);
+
+  let mut exr = Vec::new();
+  HdrFormat::OpenExr.write(&mut exr, &hdr).unwrap();
+  assert!(exr.starts_with(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]));
+  // Offsets and scanline headers come on top of the pixels and attributes.
+  assert!(exr.len() > pixel_bytes + (scene.height * 16) as usize);
+
+  assert_eq!(HdrFormat::from_path("out.EXR"), Some(HdrFormat::OpenExr));
+  assert_eq!(HdrFormat::from_path("out.png"), None);
+}
+
+#[test]
+fn test_tone_mappers_compress_highlights() {
+  use scene::ToneMapper;
+
+  let gray = |v: f32| Color { red: v, green: v, blue: v };
+  let mappers = [ToneMapper::Exposure, ToneMapper::Reinhard, ToneMapper::Aces];
+  for &mapper in &mappers {
+    let mapped: Vec<f32> = [0.0, 0.1, 0.5, 1.0, 4.0, 100.0]
+      .iter()
+      .map(|&v| mapper.apply(gray(v), 0.0).red)
+      .collect();
+    assert_eq!(mapped[0], 0.0);
+    assert!(mapped.windows(2).all(|w| w[0] <= w[1]));
+    assert!(mapped.iter().all(|&v| v <= 1.0));
+  }
+
+  // Exposure alone only clips, while the curves keep bright values apart.
+  let exposure = |v: f32| ToneMapper::Exposure.apply(gray(v), 0.0).red;
+  assert_eq!(exposure(4.0), exposure(100.0));
+  assert_eq!(ToneMapper::Exposure.apply(gray(0.25), 1.0).red, 0.5);
+  for &mapper in &mappers[1..] {
+    assert!(mapper.apply(gray(4.0), 0.0).red < mapper.apply(gray(100.0), 0.0).red);
+  }
+
+  assert_eq!("ACES".parse(), Ok(ToneMapper::Aces));
+  assert!("filmic".parse::<ToneMapper>().is_err());
+}
+
+#[test]
+fn test_srgb_encoding_and_linear_textures() {
+  use scene::{Coloration, Element};
+  use rendering::TextureCoords;
+  use image::{Pixel, Rgba};
+  use std::env;
+  use std::fs::File;
+  use std::io::Write;
+
+  for level in 0..256 {
+    let rgba = Rgba::from_channels(level as u8, level as u8, level as u8, 255);
+    assert_eq!(Color::from_rgba(rgba).to_rgba(), rgba);
+  }
+  // Linear 0.5 encodes to 187.5 in sRGB and must round up rather than truncate.
+  let half = Color { red: 0.5, green: 0.5, blue: 0.5 };
+  assert_eq!(half.to_rgba().data[0], 188);
+  assert_eq!(Color::from_rgba(Rgba::from_channels(188, 0, 0, 255)).red,
+             ((188.0 / 255.0 + 0.055) / 1.055f32).powf(2.4));
+
+  // Ordered dithering averages to the unquantized value over each 4x4 tile.
+  let gray = Color::from_rgba(Rgba::from_channels(100, 0, 0, 255)).red * 0.5 +
+             Color::from_rgba(Rgba::from_channels(101, 0, 0, 255)).red * 0.5;
+  let color = Color { red: gray, green: gray, blue: gray };
+  let levels: Vec<u8> = (0..16).map(|i| color.to_rgba_dithered(i % 4, i / 4).data[0]).collect();
+  assert!(levels.iter().all(|&l| l == 100 || l == 101));
+  let mean = levels.iter().map(|&l| l as f32).sum::<f32>() / 16.0;
+  assert!((mean - 100.5).abs() <= 1.0 / 16.0);
+
+  let texture_path = env::temp_dir().join("raytracer_test_linear_texture.png");
+  image::ImageBuffer::from_pixel(2, 2, Rgba::from_channels(128u8, 128u8, 255u8, 255u8))
+    .save(&texture_path)
+    .unwrap();
+  let scene_path = env::temp_dir().join("raytracer_test_linear_texture.yml");
+  write!(File::create(&scene_path).unwrap(),
+         r#"
+width: 4
+height: 4
+max_recursion_depth: 1
+shadow_bias: 0.0001
+camera:
+  position: {{ x: 0, y: 0, z: 0 }}
+  look_at: {{ x: 0, y: 0, z: -1 }}
+  up: {{ x: 0, y: 1, z: 0 }}
+  fov: 90
+lights: []
+elements:
+  - Sphere:
+      center: {{ x: 0, y: 0, z: -3 }}
+      radius: 1
+      material:
+        coloration:
+          Texture: "{0}"
+        albedo: 0.5
+        surface: Diffuse
+  - Sphere:
+      center: {{ x: 0, y: 0, z: -3 }}
+      radius: 1
+      material:
+        coloration:
+          Texture:
+            path: "{0}"
+            linear: true
+        albedo: 0.5
+        surface: Diffuse
+"#,
+         texture_path.display())
+    .unwrap();
+  let scene = Scene::load(&scene_path).unwrap();
+  let colors: Vec<Color> = scene.elements
+    .iter()
+    .map(|element| match (element, &element.material().coloration) {
+      (&Element::Sphere(_), coloration @ &Coloration::Texture(_)) => {
+        coloration.color(&TextureCoords { x: 0.25, y: 0.25 }, &point::Point::zero(), 0.0)
+      }
+      _ => panic!("expected textured spheres"),
+    })
+    .collect();
+  assert!((colors[0].red - 0.2158605).abs() < 1e-5);
+  assert!((colors[1].red - 128.0 / 255.0).abs() < 1e-6);
+  assert_eq!(colors[1].blue, 1.0);
+}
+
+#[test]
+fn test_texture_filtering() {
+  use scene::{Texture, TextureFilter};
+  use rendering::TextureCoords;
+  use image::{DynamicImage, ImageBuffer, Rgba};
+
+  // A checkerboard of single black and white texels.
+  let checker = DynamicImage::ImageRgba8(ImageBuffer::from_fn(8, 8, |x, y| {
+    let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+    Rgba { data: [value, value, value, 255] }
+  }));
+  let texture = |filter| Texture::new(&checker, true, filter);
+  let at = |x: f32, y: f32| TextureCoords { x, y };
+
+  // Nearest picks whole texels and repeats outside [0, 1).
+  let nearest = texture(TextureFilter::Nearest);
+  assert_eq!(nearest.color(&at(0.01, 0.01), 0.0).red, 1.0);
+  assert_eq!(nearest.color(&at(0.14, 0.01), 0.0).red, 0.0);
+  assert_eq!(nearest.color(&at(-0.01, 0.01), 0.0).red, 0.0);
+  assert_eq!(nearest.color(&at(1.01, 0.01), 0.0).red, 1.0);
+
+  // Bilinear is exact at texel centers and blends halfway between them.
+  let bilinear = texture(TextureFilter::Bilinear);
+  assert!((bilinear.color(&at(0.0625, 0.0625), 0.0).red - 1.0).abs() < 1e-6);
+  assert!((bilinear.color(&at(0.125, 0.0625), 0.0).red - 0.5).abs() < 1e-6);
+
+  // Trilinear matches bilinear up close and fades to the average when a pixel covers
+  // several texels.
+  let trilinear = texture(TextureFilter::Trilinear);
+  assert!((trilinear.color(&at(0.0625, 0.0625), 0.0).red - 1.0).abs() < 1e-6);
+  assert!((trilinear.color(&at(0.0625, 0.0625), 0.25).red - 0.5).abs() < 1e-6);
+  assert!((trilinear.color(&at(0.3, 0.7), 10.0).red - 0.5).abs() < 1e-6);
+  let halfway = trilinear.color(&at(0.0625, 0.0625), 1.5 / 8.0).red;
+  assert!(halfway > 0.5 && halfway < 1.0);
+
+  // Mip levels of odd-sized textures still take in their last row and column.
+  let edges = DynamicImage::ImageRgba8(ImageBuffer::from_fn(3, 3, |x, y| {
+    let value = if x == 2 || y == 2 { 255 } else { 0 };
+    Rgba { data: [value, value, value, 255] }
+  }));
+  let trilinear = Texture::new(&edges, true, TextureFilter::Trilinear);
+  assert!((trilinear.color(&at(0.5, 0.5), 10.0).red - 5.0 / 9.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_texture_wrap_and_transform() {
+  use scene::{Texture, TextureFilter, TextureWrap, UvTransform};
+  use rendering::TextureCoords;
+  use image::{DynamicImage, ImageBuffer, Rgba};
+
+  // Four texels in a row, with values 0, 1/3, 2/3 and 1.
+  let ramp = DynamicImage::ImageRgba8(ImageBuffer::from_fn(4, 1, |x, _| {
+    let value = (x * 85) as u8;
+    Rgba { data: [value, value, value, 255] }
+  }));
+  let mut texture = Texture::new(&ramp, true, TextureFilter::Nearest);
+  let red_at = |texture: &Texture, x: f32| texture.color(&TextureCoords { x, y: 0.5 }, 0.0).red;
+
+  assert_eq!(red_at(&texture, 1.1), red_at(&texture, 0.1));
+  assert_eq!(red_at(&texture, -0.1), 1.0);
+
+  texture.wrap = TextureWrap::MirrorRepeat;
+  assert_eq!(red_at(&texture, 1.1), 1.0);
+  assert_eq!(red_at(&texture, 1.9), 0.0);
+  assert_eq!(red_at(&texture, -0.1), 0.0);
+  assert_eq!(red_at(&texture, 2.1), 0.0);
+
+  texture.wrap = TextureWrap::ClampToEdge;
+  assert_eq!(red_at(&texture, 5.0), 1.0);
+  assert_eq!(red_at(&texture, -5.0), 0.0);
+
+  // Scaling by 4 shows the whole ramp in the first quarter; the offset shifts it along.
+  texture.transform = UvTransform {
+    scale: TextureCoords { x: 4.0, y: 1.0 },
+    offset: TextureCoords { x: 0.5, y: 0.0 },
+    rotation: 0.0,
+  };
+  assert!((red_at(&texture, 0.0) - 2.0 / 3.0).abs() < 1e-6);
+  assert_eq!(red_at(&texture, 0.2), 1.0);
+
+  // A quarter turn maps the surface's y direction onto the texture's -x direction.
+  texture.transform = UvTransform {
+    scale: TextureCoords { x: 1.0, y: 1.0 },
+    offset: TextureCoords { x: 0.9, y: 0.5 },
+    rotation: 90.0,
+  };
+  let rotated = texture.transform.apply(&TextureCoords { x: 0.0, y: 0.25 });
+  assert!((rotated.x - 0.65).abs() < 1e-6 && (rotated.y - 0.5).abs() < 1e-6);
+  assert!((texture.color(&TextureCoords { x: 0.0, y: 0.25 }, 0.0).red - 2.0 / 3.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_procedural_colorations() {
+  use scene::Coloration;
+  use rendering::TextureCoords;
+  use point::Point;
+
+  let at = |coloration: &Coloration, x: f32, y: f32, position: Point| {
+    coloration.color(&TextureCoords { x, y }, &position, 0.0)
+  };
+  let origin = Point::zero();
+
+  let checker: Coloration = serde_yaml::from_str("
+Checker:
+  even: { red: 1.0, green: 1.0, blue: 1.0 }
+  odd: { red: 0.0, green: 0.0, blue: 0.0 }
+  scale: 4
+").unwrap();
+  assert_eq!(at(&checker, 0.1, 0.1, origin).red, 1.0);
+  assert_eq!(at(&checker, 0.3, 0.1, origin).red, 0.0);
+  assert_eq!(at(&checker, 0.3, 0.3, origin).red, 1.0);
+
+  // In position space, a floor lying exactly on a cell boundary still gets clean cells.
+  let solid: Coloration = serde_yaml::from_str("
+Checker:
+  even: { red: 1.0, green: 1.0, blue: 1.0 }
+  odd: { red: 0.0, green: 0.0, blue: 0.0 }
+  space: Position
+").unwrap();
+  let floor = |x: f64, y: f64| at(&solid, 0.0, 0.0, Point { x, y, z: 0.5 }).red;
+  assert_eq!(floor(0.5, -2.0), floor(0.5, -2.0 - 1e-9));
+  assert_eq!(floor(0.5, -2.0), floor(0.5, -2.0 + 1e-9));
+  assert!(floor(0.5, -2.0) != floor(1.5, -2.0));
+
+  let gradient: Coloration = serde_yaml::from_str("
+Gradient:
+  from: { red: 0.0, green: 0.0, blue: 0.0 }
+  to: { red: 1.0, green: 1.0, blue: 1.0 }
+  start: { x: 0.5, y: 0.5, z: 0.0 }
+  end: { x: 1.0, y: 0.5, z: 0.0 }
+  shape: Radial
+").unwrap();
+  assert_eq!(at(&gradient, 0.5, 0.5, origin).red, 0.0);
+  assert!((at(&gradient, 0.5, 0.25, origin).red - 0.5).abs() < 1e-6);
+  assert_eq!(at(&gradient, 0.0, 0.0, origin).red, 1.0);
+
+  // Noise varies smoothly and stays between its two colors.
+  for name in &["Noise", "Marble", "Wood"] {
+    let pattern: Coloration = serde_yaml::from_str(&format!("
+{}:
+  from: {{ red: 0.0, green: 0.0, blue: 0.0 }}
+  to: {{ red: 1.0, green: 1.0, blue: 1.0 }}
+  scale: 3
+  space: Position
+",
+                                                           name))
+      .unwrap();
+    let values: Vec<f32> = (0..200)
+      .map(|i| {
+        let position = Point { x: i as f64 * 0.013, y: 0.37, z: -0.21 };
+        at(&pattern, 0.0, 0.0, position).red
+      })
+      .collect();
+    assert!(values.iter().all(|&v| (0.0..=1.0).contains(&v)), "{} out of range", name);
+    let spread = values.iter().cloned().fold(0.0, f32::max) -
+                 values.iter().cloned().fold(1.0, f32::min);
+    assert!(spread > 0.2, "{} is flat", name);
+  }
+  let noise_at = |x: f64, y: f64, z: f64| noise::perlin(&Point { x, y, z });
+  assert_eq!(noise_at(3.0, -2.0, 7.0), 0.0);
+  assert!((noise_at(0.5, 0.5, 0.5) - noise_at(0.5001, 0.5, 0.5)).abs() < 1e-3);
+}
+
+#[test]
+fn test_normal_and_bump_maps_tilt_shading_normal() {
+  use scene::{BumpMap, Element, Light, NormalMap, Plane, SphericalLight, Texture, TextureFilter};
+  use rendering::{Intersectable, Ray, SurfaceNormals, TraceState, shade_diffuse, shading_normal};
+  use sampling::Rng;
+  use image::{DynamicImage, ImageBuffer, Rgba};
+  use point::Point;
+  use vector::Vector3;
+
+  let image = |width, height, texel: &dyn Fn(u32) -> [u8; 3]| {
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, _| {
+      let [red, green, blue] = texel(x);
+      Rgba { data: [red, green, blue, 255] }
+    }))
+  };
+  let flat_normals = image(4, 4, &|_| [128, 128, 255]);
+  let tilted_normals = image(4, 4, &|_| [218, 128, 218]);
+  let ramp = image(16, 1, &|x| [(x * 17) as u8; 3]);
+  let with_maps = |normal_map: Option<&DynamicImage>, bump_map: Option<&DynamicImage>| {
+    let mut material = diffuse_material(0.8, 0.8, 0.8);
+    material.normal_map = normal_map.map(|image| NormalMap {
+      texture: Texture::new(image, true, TextureFilter::Nearest),
+      strength: 1.0,
+    });
+    material.bump_map = bump_map.map(|image| BumpMap {
+      texture: Texture::new(image, true, TextureFilter::Bilinear),
+      height: 0.1,
+    });
+    test_scene(vec![Element::Plane(Plane {
+                      origin: Point { x: 0.0, y: -1.0, z: 0.0 },
+                      normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+                      transform: None,
+                      material,
+                    })],
+               vec![])
+  };
+  let ray = Ray {
+    origin: Point { x: 0.5, y: 1.0, z: 0.5 },
+    direction: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+  };
+  let shade = |scene: &Scene| {
+    let intersection = scene.trace(&ray).unwrap();
+    let hit = ray.origin + ray.direction * intersection.distance;
+    let normal = intersection.element.surface_normal(&hit, intersection.face);
+    let shading = shading_normal(scene, &intersection, hit, normal, ray.direction);
+    // The direction across the surface in which the horizontal texture coordinate grows.
+    let coords = |p: Point| intersection.element.texture_coords(&p, intersection.face).x;
+    let along_u = if coords(hit + Vector3 { x: 1e-3, y: 0.0, z: 0.0 }) > coords(hit) {
+      Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+    } else {
+      Vector3 { x: -1.0, y: 0.0, z: 0.0 }
+    };
+    (normal, shading, along_u)
+  };
+
+  let (normal, shading, _) = shade(&with_maps(None, None));
+  assert_eq!((normal.x, normal.y, normal.z), (shading.x, shading.y, shading.z));
+  // 128 is as close to 0.5 as eight bits get.
+  let (normal, shading, _) = shade(&with_maps(Some(&flat_normals), None));
+  assert!((normal - shading).length() < 0.01);
+
+  // The tilted map leans 45 degrees towards increasing u.
+  let (normal, shading, along_u) = shade(&with_maps(Some(&tilted_normals), None));
+  assert!((shading.length() - 1.0).abs() < 1e-9);
+  assert!((shading.dot(&normal) - 0.5f64.sqrt()).abs() < 0.02);
+  assert!((shading.dot(&along_u) - 0.5f64.sqrt()).abs() < 0.02);
+
+  // Height rising with u tilts the normal back towards decreasing u: a slope of 16/15 per
+  // unit of u, times the height scale.
+  let (normal, shading, along_u) = shade(&with_maps(None, Some(&ramp)));
+  let slope = 0.1 * 16.0 / 15.0;
+  assert!(shading.dot(&along_u) < 0.0);
+  assert!((-shading.dot(&along_u) / shading.dot(&normal) - slope).abs() < 0.01);
+
+  // Light from below the floor doesn't reach it, however far the shading normal leans.
+  let scene = with_maps(None, None);
+  let intersection = scene.trace(&ray).unwrap();
+  let hit = ray.origin + ray.direction * intersection.distance;
+  let lit_from = |position: Point| {
+    let light = Light::Spherical(SphericalLight {
+      position,
+      color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+      intensity: 100.0,
+      spectrum: None,
+      radius: 0.0,
+    });
+    let scene = test_scene(vec![], vec![light]);
+    let normals = SurfaceNormals {
+      geometric: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+      shading: Vector3 { x: 0.98, y: 0.2, z: 0.0 }.normalize(),
+    };
+    let state = TraceState::new(None);
+    shade_diffuse(&scene, &state, &intersection, hit, normals, ray.direction, &mut Rng::new(1))
+      .red
+  };
+  assert!(lit_from(hit + Vector3 { x: 5.0, y: 0.5, z: 0.0 }) > 0.0);
+  assert_eq!(0.0, lit_from(hit + Vector3 { x: 5.0, y: -0.5, z: 0.0 }));
+}
+
+#[test]
+fn test_element_transforms() {
+  use scene::Element;
+  use matrix::Matrix4;
+  use transform::Transform;
+  use rendering::{Intersectable, Ray};
+  use point::Point;
+  use vector::Vector3;
+
+  let element = |yaml: &str| -> Element { serde_yaml::from_str(yaml).unwrap() };
+  let ray = |origin: Point, direction: Vector3| Ray { origin, direction: direction.normalize() };
+
+  // A unit sphere stretched into an ellipsoid and moved away from the origin.
+  let ellipsoid = element("
+Sphere:
+  center: { x: 0, y: 0, z: 0 }
+  radius: 1
+  transform:
+    scale: { x: 2, y: 1, z: 1 }
+    translate: { x: 0, y: 0, z: -5 }
+  material: { coloration: { Color: { red: 1, green: 1, blue: 1 } }, albedo: 0.5, surface: Diffuse }
+");
+  let along_x = ray(Point { x: -10.0, y: 0.0, z: -5.0 }, Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+  assert!((ellipsoid.intersect(&along_x).unwrap().distance - 8.0).abs() < 1e-9);
+  let along_z = ray(Point::zero(), Vector3 { x: 0.0, y: 0.0, z: -1.0 });
+  assert!((ellipsoid.intersect(&along_z).unwrap().distance - 4.0).abs() < 1e-9);
+
+  // Normals stay perpendicular to the stretched surface: at (x, y) on the ellipse
+  // x^2 / 4 + y^2 = 1 the normal points along (x / 4, y).
+  let (x, y) = (2.0 * 0.6, 0.8);
+  let hit = Point { x, y, z: -5.0 };
+  let normal = ellipsoid.surface_normal(&hit, 0);
+  let expected = Vector3 { x: x / 4.0, y, z: 0.0 }.normalize();
+  assert!((normal - expected).length() < 1e-9);
+
+  let bounds = ellipsoid.bounding_box().unwrap();
+  assert!((bounds.min.x + 2.0).abs() < 1e-9 && (bounds.max.z + 4.0).abs() < 1e-9);
+
+  // Rotations, axis-angle and matrices compose in the order they are listed.
+  let rotated = element("
+Box:
+  min: { x: 0, y: 0, z: 0 }
+  max: { x: 1, y: 1, z: 1 }
+  transform:
+    scale: 2
+    rotate: { x: 0, y: 0, z: 90 }
+    axis_angle: { axis: { x: 0, y: 0, z: 1 }, angle: -90 }
+    matrix: [[1, 0, 0, 3], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]
+  material: { coloration: { Color: { red: 1, green: 1, blue: 1 } }, albedo: 0.5, surface: Diffuse }
+");
+  let bounds = rotated.bounding_box().unwrap();
+  assert!((bounds.min.x - 3.0).abs() < 1e-9 && (bounds.max.x - 5.0).abs() < 1e-9);
+  assert!(bounds.min.y.abs() < 1e-9 && (bounds.max.y - 2.0).abs() < 1e-9);
+
+  let quarter_turn = Transform::rotation_euler(&Vector3 { x: 0.0, y: 0.0, z: 90.0 });
+  let turned = quarter_turn.vector_to_world(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+  assert!((turned - Vector3 { x: 0.0, y: 1.0, z: 0.0 }).length() < 1e-9);
+  let round_trip = quarter_turn.then(&Transform::translation(Vector3::from_one(1.0)))
+    .point_to_local(&Point { x: 1.0, y: 2.0, z: 1.0 });
+  assert!((round_trip - Point { x: 1.0, y: 0.0, z: 0.0 }).length() < 1e-9);
+
+  // Normals follow the whole chain, however its parts were built.
+  let shear = Matrix4::from_linear([[1.0, 0.5, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+  let chain = Transform::scaling(Vector3 { x: 0.5, y: 2.0, z: -1.0 })
+    .unwrap()
+    .then(&quarter_turn)
+    .then(&Transform::translation(Vector3::from_one(1.0)))
+    .then(&Transform::new(shear).unwrap());
+  let normal = Vector3 { x: 0.3, y: -0.4, z: 0.5 };
+  let expected = chain.matrix().transform_normal(&normal);
+  assert!((chain.normal_to_world(&normal) - expected).length() < 1e-9);
+
+  for &(yaml, message) in &[("scale: 0", "zero"),
+                            ("matrix: [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [1, 0, 0, 1]]",
+                             "affine"),
+                            ("matrix: [[1, 0, 0, 0], [2, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]",
+                             "invertible")] {
+    let error = serde_yaml::from_str::<Transform>(yaml).unwrap_err().to_string();
+    assert!(error.contains(message), "{}", error);
+  }
+}
+
+#[test]
+fn test_matrix4() {
+  use matrix::Matrix4;
+  use point::Point;
+  use vector::Vector3;
+
+  let close = |a: &Matrix4, b: &Matrix4| {
+    a.rows.iter().flat_map(|r| r.iter()).zip(b.rows.iter().flat_map(|r| r.iter()))
+      .all(|(x, y)| (x - y).abs() < 1e-9)
+  };
+
+  let m: Matrix4 = serde_yaml::from_str("
+- [2, 0, 1, 3]
+- [0, 1, 0, -1]
+- [1, 0, 3, 2]
+- [0, 0, 0, 1]
+").unwrap();
+  assert_eq!(m.rows[0], [2.0, 0.0, 1.0, 3.0]);
+  assert!(m.is_affine());
+  assert_eq!(m.transpose().rows[3], [3.0, -1.0, 2.0, 1.0]);
+  assert_eq!(m.transpose().transpose(), m);
+
+  let inverse = m.inverse().unwrap();
+  assert!(close(&(m * inverse), &Matrix4::identity()));
+  assert!(close(&(inverse * m), &Matrix4::identity()));
+  assert!(Matrix4::scaling(Vector3 { x: 1.0, y: 0.0, z: 1.0 }).inverse().is_none());
+
+  // Translation moves points but not directions, and products apply right to left.
+  let moved = Matrix4::translation(Vector3 { x: 1.0, y: 2.0, z: 3.0 }) *
+              Matrix4::scaling(Vector3::from_one(2.0));
+  let point = moved.transform_point(&Point { x: 1.0, y: 1.0, z: 1.0 });
+  assert!((point - Point { x: 3.0, y: 4.0, z: 5.0 }).length() < 1e-9);
+  let vector = moved.transform_vector(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+  assert!((vector - Vector3 { x: 2.0, y: 0.0, z: 0.0 }).length() < 1e-9);
+
+  // Projective matrices divide through by w.
+  let mut perspective = Matrix4::identity();
+  perspective.rows[3] = [0.0, 0.0, 1.0, 0.0];
+  assert!(!perspective.is_affine());
+  let projected = perspective.transform_point(&Point { x: 2.0, y: 4.0, z: 2.0 });
+  assert!((projected - Point { x: 1.0, y: 2.0, z: 1.0 }).length() < 1e-9);
+
+  // A plane squashed along x tilts its normals towards x, and mirroring keeps them outward.
+  let squash = Matrix4::scaling(Vector3 { x: 0.5, y: 1.0, z: 1.0 });
+  let normal = squash.transform_normal(&Vector3 { x: 1.0, y: 1.0, z: 0.0 }.normalize());
+  assert!((normal - Vector3 { x: 2.0, y: 1.0, z: 0.0 }.normalize()).length() < 1e-9);
+  let mirror = Matrix4::scaling(Vector3 { x: -1.0, y: 1.0, z: 1.0 });
+  let mirrored = mirror.transform_normal(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+  assert!((mirrored - Vector3 { x: -1.0, y: 0.0, z: 0.0 }).length() < 1e-9);
+  // Without inverting, the normal matrix still points the way the inverse transpose does.
+  let tilted = Vector3 { x: 0.3, y: -0.4, z: 0.5 };
+  let expected = inverse.transpose().transform_vector(&tilted).normalize();
+  assert!((m.transform_normal(&tilted) - expected).length() < 1e-9);
+
+  let euler = Matrix4::rotation_euler(&Vector3 { x: 0.0, y: 0.0, z: 90.0 });
+  let axis = Matrix4::rotation_axis(&Vector3 { x: 0.0, y: 0.0, z: 2.0 }, 90.0);
+  assert!(close(&euler, &axis));
+  assert!(close(&euler.inverse().unwrap(), &euler.transpose()));
+}
+
+#[test]
+fn test_quaternion() {
+  use quaternion::Quaternion;
+  use transform::Transform;
+  use vector::Vector3;
+
+  let x = Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+  let z_axis = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+  let quarter = Quaternion::from_axis_angle(&z_axis, 90.0);
+  assert!((quarter.length() - 1.0).abs() < 1e-12);
+  assert!((quarter.rotate(&x) - Vector3 { x: 0.0, y: 1.0, z: 0.0 }).length() < 1e-9);
+  assert!((quarter.conjugate().rotate(&quarter.rotate(&x)) - x).length() < 1e-9);
+
+  // Products compose rotations, applying the right-hand one first.
+  let half = quarter * quarter;
+  assert!((half.rotate(&x) + x).length() < 1e-9);
+  let tilt = Quaternion::from_axis_angle(&x, 90.0);
+  let v = Vector3 { x: 0.3, y: -0.4, z: 0.5 };
+  assert!(((tilt * quarter).rotate(&v) - tilt.rotate(&quarter.rotate(&v))).length() < 1e-9);
+
+  // The matrix form rotates the same way.
+  let matrix = (tilt * quarter).to_matrix();
+  assert!((matrix.transform_vector(&v) - (tilt * quarter).rotate(&v)).length() < 1e-9);
+
+  // Slerp turns at a constant rate, and goes the short way even from the negated end.
+  let identity = Quaternion::identity();
+  for &(t, degrees) in &[(0.0, 0.0), (0.25, 22.5), (0.5, 45.0), (1.0, 90.0)] {
+    let expected = Quaternion::from_axis_angle(&z_axis, degrees);
+    for &end in &[quarter, quarter * -1.0] {
+      let between = identity.slerp(&end, t);
+      assert!((between.rotate(&x) - expected.rotate(&x)).length() < 1e-9);
+    }
+  }
+  let nearly = Quaternion::from_axis_angle(&z_axis, 1.0);
+  assert!((identity.slerp(&nearly, 0.5).length() - 1.0).abs() < 1e-12);
+
+  // Scene files can rotate elements by a quaternion, which needn't be normalized.
+  let turn: Transform = serde_yaml::from_str("quaternion: { w: 2, x: 0, y: 0, z: 2 }").unwrap();
+  let turned = turn.vector_to_world(&x);
+  assert!((turned - Vector3 { x: 0.0, y: 1.0, z: 0.0 }).length() < 1e-9);
+  let error = serde_yaml::from_str::<Transform>("quaternion: { w: 0, x: 0, y: 0, z: 0 }")
+    .unwrap_err()
+    .to_string();
+  assert!(error.contains("zero"), "{}", error);
+}
+
+#[test]
+fn test_blinn_phong_highlights() {
+  use scene::{Coloration, Element, Light, Material, Plane, SphericalLight, SurfaceType};
+  use rendering::{Ray, SurfaceNormals, TraceState, shade_diffuse};
+  use sampling::Rng;
+  use point::Point;
+  use vector::Vector3;
+  use std::f64::consts::PI;
+
+  // A black floor with white highlights, under a point light 30 degrees from overhead.
+  let direction = |theta: f64, phi: f64| {
+    Vector3 { x: theta.sin() * phi.cos(), y: theta.cos(), z: theta.sin() * phi.sin() }
+  };
+  let to_light = direction(PI / 6.0, 0.0);
+  let floor = |shininess: f32| {
+    let plane = Element::Plane(Plane {
+      origin: Point::zero(),
+      normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+      transform: None,
+      material: Material {
+        specular: Color { red: 1.0, green: 1.0, blue: 1.0 },
+        shininess,
+        ..diffuse_material(0.0, 0.0, 0.0)
+      },
+    });
+    let light = Light::Spherical(SphericalLight {
+      position: Point::zero() + to_light * 5.0,
+      color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+      intensity: 100.0,
+      spectrum: None,
+      radius: 0.0,
+    });
+    test_scene(vec![plane], vec![light])
+  };
+  // The light reflected towards `to_eye`, over the irradiance: the specular BRDF.
+  let brdf = |scene: &Scene, to_eye: Vector3| {
+    let ray = Ray {
+      origin: Point::zero() + to_eye * 5.0,
+      direction: -to_eye,
+    };
+    let intersection = scene.trace(&ray).unwrap();
+    let hit = ray.origin + ray.direction * intersection.distance;
+    let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+    let normals = SurfaceNormals { geometric: up, shading: up };
+    let state = TraceState::new(None);
+    let radiance =
+      shade_diffuse(scene, &state, &intersection, hit, normals, ray.direction, &mut Rng::new(1));
+    let irradiance = scene.lights[0].sample(&hit, (0.5, 0.5)).intensity * to_light.y as f32;
+    radiance.red as f64 / irradiance as f64
+  };
+
+  let mut falloffs = Vec::new();
+  let mut albedos = Vec::new();
+  for &shininess in &[8.0, 64.0, 512.0] {
+    let scene = floor(shininess);
+    // The highlight is brightest in the mirror direction.
+    let mirror = brdf(&scene, direction(PI / 6.0, PI));
+    assert!(mirror > brdf(&scene, direction(PI / 6.0 - 0.05, PI)));
+    assert!(mirror > brdf(&scene, direction(PI / 6.0 + 0.05, PI)));
+    assert!(mirror > brdf(&scene, direction(PI / 6.0, PI - 0.05)));
+    falloffs.push(brdf(&scene, direction(PI / 6.0 + 0.3, PI)) / mirror);
+
+    // However sharp, the lobe never reflects more light than falls on it.
+    let (rows, columns) = (64, 128);
+    let mut albedo = 0.0;
+    for row in 0..rows {
+      let theta = (row as f64 + 0.5) / rows as f64 * PI / 2.0;
+      for column in 0..columns {
+        let phi = (column as f64 + 0.5) / columns as f64 * 2.0 * PI;
+        let solid_angle = theta.sin() * (PI / 2.0 / rows as f64) * (2.0 * PI / columns as f64);
+        albedo += brdf(&scene, direction(theta, phi)) * theta.cos() * solid_angle;
+      }
+    }
+    albedos.push(albedo);
+  }
+  // Sharper highlights are narrower, but normalization keeps them about as bright overall.
+  assert!(falloffs[0] > falloffs[1] && falloffs[1] > falloffs[2], "{:?}", falloffs);
+  assert!(falloffs[2] < 0.01, "{:?}", falloffs);
+  assert!(albedos.iter().all(|&a| 0.7 < a && a < 1.0), "{:?}", albedos);
+
+  // Refractive surfaces get the same highlights, and no diffuse light.
+  let mut scene = floor(64.0);
+  let views = [direction(PI / 6.0, PI), direction(0.0, 0.0), direction(1.0, 2.0)];
+  let highlights: Vec<f64> = views.iter().map(|&view| brdf(&scene, view)).collect();
+  if let Element::Plane(ref mut plane) = scene.elements.edit()[0] {
+    plane.material.coloration = Coloration::Color(Color { red: 1.0, green: 1.0, blue: 1.0 });
+    plane.material.surface = SurfaceType::Refractive {
+      index: 1.5,
+      transparency: 1.0,
+      roughness: 0.0,
+      absorption: None,
+      dispersion: None,
+    };
+  }
+  assert_eq!(highlights, views.iter().map(|&view| brdf(&scene, view)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_principled_brdf() {
+  use brdf::Principled;
+  use sampling::Rng;
+  use scene::{Material, SurfaceType};
+  use vector::Vector3;
+  use std::f64::consts::PI;
+
+  let white = Color { red: 1.0, green: 1.0, blue: 1.0 };
+  let normal = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+  let direction = |theta: f64, phi: f64| {
+    Vector3 { x: theta.sin() * phi.cos(), y: theta.sin() * phi.sin(), z: theta.cos() }
+  };
+  let outgoing = direction(0.6, 0.0);
+
+  // Swapping the viewer and the light leaves the BRDF unchanged.
+  let brdf = Principled::new(Color { red: 0.8, green: 0.4, blue: 0.2 }, 0.3, 0.4, 0.5);
+  let incoming = direction(1.1, 2.0);
+  let there = brdf.evaluate(&normal, &outgoing, &incoming);
+  let back = brdf.evaluate(&normal, &incoming, &outgoing);
+  assert!((there.red - back.red).abs() < 1e-5 && (there.blue - back.blue).abs() < 1e-5);
+  assert_eq!(brdf.evaluate(&normal, &outgoing, &-incoming).red, 0.0);
+
+  // The sampler's average weight is the reflected fraction of light, which must agree with
+  // integrating the BRDF over the hemisphere, and never exceed one.
+  let mut rng = Rng::new(7);
+  for &(metallic, roughness) in &[(0.0, 0.3), (1.0, 0.2), (1.0, 0.7), (0.5, 1.0)] {
+    let brdf = Principled::new(white, metallic, roughness, 0.5);
+    let samples = 200_000;
+    let mut sampled = 0.0;
+    for _ in 0..samples {
+      if let Some(sample) = brdf.sample(&normal, &outgoing, &mut rng) {
+        sampled += sample.weight.green as f64;
+      }
+    }
+    sampled /= samples as f64;
+
+    let steps = 400;
+    let mut integrated = 0.0;
+    for i in 0..steps {
+      for j in 0..steps {
+        let theta = (i as f64 + 0.5) / steps as f64 * PI / 2.0;
+        let phi = (j as f64 + 0.5) / steps as f64 * 2.0 * PI;
+        let f = brdf.evaluate(&normal, &outgoing, &direction(theta, phi)).green as f64;
+        integrated += f * theta.cos() * theta.sin();
+      }
+    }
+    integrated *= (PI / 2.0 / steps as f64) * (2.0 * PI / steps as f64);
+
+    assert!(sampled <= 1.0 && integrated <= 1.0, "{} {}", sampled, integrated);
+    assert!((sampled - integrated).abs() < 0.02,
+            "metallic {} roughness {}: sampled {} integrated {}",
+            metallic,
+            roughness,
+            sampled,
+            integrated);
+  }
+
+  // A smooth metal is nearly a mirror: its specular samples land on the reflection.
+  let mirror = Principled::new(white, 1.0, 0.0, 0.5);
+  let sample = mirror.sample_specular(&normal, &outgoing, &mut rng).unwrap();
+  let reflected = Vector3 { x: -outgoing.x, y: -outgoing.y, z: outgoing.z };
+  assert!((sample.direction - reflected).length() < 0.01);
+  assert!((sample.weight.red - 1.0).abs() < 0.01);
+
+  let material: Material = serde_yaml::from_str("
+coloration: { Color: { red: 0.9, green: 0.6, blue: 0.2 } }
+albedo: 1
+surface: { Principled: { metallic: 1 } }
+").unwrap();
+  match material.surface {
+    SurfaceType::Principled { metallic, roughness, specular } => {
+      assert_eq!((metallic, roughness, specular), (1.0, 0.5, 0.5));
+    }
+    _ => panic!("expected a principled surface"),
+  }
+}
+
+#[test]
+fn test_glossy_reflection_and_refraction() {
+  use rendering::Ray;
+  use sampling::Rng;
+  use scene::SurfaceType;
+  use point::Point;
+  use vector::Vector3;
+
+  let normal = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+  let incident = Vector3 { x: 1.0, y: -1.0, z: 0.0 }.normalize();
+  let mirror = Vector3 { x: 1.0, y: 1.0, z: 0.0 }.normalize();
+  let hit = Point::zero();
+  let mut rng = Rng::new(3);
+
+  // The mean angle from the mirror direction grows with roughness, and rays never dip
+  // below the surface.
+  let spread = |roughness: f32, rng: &mut Rng| {
+    let mut total = 0.0;
+    for _ in 0..2000 {
+      let ray = Ray::create_glossy_reflection(normal, incident, hit, 1e-6, roughness, rng);
+      assert!(ray.direction.dot(&normal) > 0.0);
+      assert!((ray.direction.length() - 1.0).abs() < 1e-9);
+      total += ray.direction.dot(&mirror).min(1.0).acos();
+    }
+    total / 2000.0
+  };
+  assert!(spread(0.0, &mut rng) < 1e-6);
+  let (smooth, rough) = (spread(0.2, &mut rng), spread(0.6, &mut rng));
+  assert!(smooth > 0.01 && rough > 2.0 * smooth, "{} {}", smooth, rough);
+
+  // Frosted glass scatters transmitted rays around the refracted direction, but they still
+  // pass through the surface, whichever side they start from.
+  let refracted = Ray::create_transmission(normal, incident, hit, 1e-6, 1.5).unwrap().direction;
+  let mut total = 0.0;
+  for _ in 0..2000 {
+    let ray = Ray::create_glossy_transmission(normal, incident, hit, 1e-6, 1.5, 0.4, &mut rng)
+      .unwrap();
+    assert!(ray.direction.dot(&normal) < 0.0);
+    total += ray.direction.dot(&refracted).min(1.0).acos();
+  }
+  assert!(total / 2000.0 > 0.01);
+  let exiting = Vector3 { x: 0.3, y: 1.0, z: 0.0 }.normalize();
+  for _ in 0..200 {
+    let ray = Ray::create_glossy_transmission(normal, exiting, hit, 1e-6, 1.5, 0.4, &mut rng)
+      .unwrap();
+    assert!(ray.direction.dot(&normal) > 0.0);
+  }
+
+  let surface: SurfaceType = serde_yaml::from_str("Reflective: { reflectivity: 0.5 }").unwrap();
+  match surface {
+    SurfaceType::Reflective { roughness, .. } => assert_eq!(roughness, 0.0),
+    _ => panic!("expected a reflective surface"),
+  }
+}
+
+#[test]
+fn test_fresnel_reflectance() {
+  use rendering::fresnel;
+  use vector::Vector3;
+
+  let normal = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+  let at = |degrees: f64| {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vector3 { x: sin, y: 0.0, z: -cos }
+  };
+  // Reflectance rises from 4% head-on to the textbook 8.9% at 60 degrees for glass, and
+  // towards everything at grazing angles, whichever way the normal is turned.
+  assert!((fresnel(at(0.0), normal, 1.5) - 0.04).abs() < 1e-6);
+  assert!((fresnel(at(60.0), normal, 1.5) - 0.0892).abs() < 1e-3);
+  assert!((fresnel(at(60.0), -normal, 1.0 / 1.5) - 0.0892).abs() < 1e-3);
+  assert!(fresnel(at(89.0), normal, 1.5) > 0.9);
+  let mut previous = 0.0;
+  for degrees in 0..90 {
+    let reflectance = fresnel(at(degrees as f64), normal, 1.5);
+    assert!(reflectance >= previous, "{} degrees", degrees);
+    previous = reflectance;
+  }
+  // Leaving glass, the same angle inside reflects as much as it does outside, up to the
+  // critical angle, past which everything is reflected.
+  let inside = |degrees: f64| fresnel(-at(degrees), normal, 1.5);
+  let refracted = (60f64.to_radians().sin() / 1.5).asin().to_degrees();
+  assert!((inside(refracted) - fresnel(at(60.0), normal, 1.5)).abs() < 1e-9);
+  assert_eq!(inside(60.0), 1.0);
+  // Between media with the same index, nothing is reflected at all.
+  assert!(fresnel(at(60.0), normal, 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_refraction_absorption_and_nested_media() {
+  use scene::{Absorption, Element, Intersection, Sphere, SurfaceType};
+  use rendering::{cast_ray, refraction, Intersectable, TraceState, Ray, WHITE};
+  use sampling::Rng;
+  use point::Point;
+  use vector::Vector3;
+
+  let at = |degrees: f64| {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vector3 { x: sin, y: 0.0, z: -cos }
+  };
+
+  let absorption = Absorption {
+    color: Color { red: 0.5, green: 1.0, blue: 0.25 },
+    density: 2.0,
+  };
+  let left = absorption.transmittance(1.5);
+  assert!((left.red - 0.125).abs() < 1e-6 && left.green == 1.0);
+
+  let refractive = |center: Point, radius: f64, index: f32, absorption: Option<Absorption>| {
+    let mut material = diffuse_material(1.0, 1.0, 1.0);
+    material.surface = SurfaceType::Refractive {
+      index,
+      transparency: 1.0,
+      roughness: 0.0,
+      absorption,
+      dispersion: None,
+    };
+    Element::Sphere(Sphere {
+      center,
+      radius,
+      transform: None,
+      material,
+    })
+  };
+
+  // A glass ball in water with the same index doesn't bend light, going in or out.
+  let water = refractive(Point::zero(), 10.0, 1.33, Some(absorption));
+  let glass = refractive(Point { x: 0.0, y: 0.0, z: -5.0 }, 1.0, 1.33, None);
+  let in_water = TraceState::default().crossing(&water, true);
+  assert_eq!(in_water.index(), 1.33);
+  assert!((in_water.attenuate(WHITE, 1.5).red - 0.125).abs() < 1e-6);
+
+  let into_glass = Ray { origin: Point::zero(), direction: at(0.0) };
+  let hit = glass.intersect(&into_glass).unwrap();
+  let intersection = Intersection::new(hit, &glass).unwrap();
+  let surface = glass.surface_normal(&Point { x: 0.0, y: 0.0, z: -4.0 }, 0);
+  let (in_glass, index) = refraction(&in_water, &intersection, &into_glass, surface);
+  assert_eq!((in_glass.index(), index), (1.33, 1.0));
+  assert_eq!(in_glass.attenuate(WHITE, 1.0).red, 1.0);
+
+  let out_of_glass = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: at(0.0) };
+  let hit = glass.intersect(&out_of_glass).unwrap();
+  let intersection = Intersection::new(hit, &glass).unwrap();
+  let surface = glass.surface_normal(&Point { x: 0.0, y: 0.0, z: -6.0 }, 0);
+  let (outside, index) = refraction(&in_glass, &intersection, &out_of_glass, surface);
+  assert_eq!((outside.index(), index), (1.33, 1.0));
+  assert!((outside.attenuate(WHITE, 1.5).red - 0.125).abs() < 1e-6);
+
+  // Seen through an absorbing ball, a lit wall loses red in proportion to the two units of
+  // ball the light crosses.
+  let tinted = Absorption {
+    color: Color { red: 0.5, green: 1.0, blue: 1.0 },
+    density: 1.0,
+  };
+  let ball = refractive(Point { x: 0.0, y: 0.0, z: -3.0 }, 1.0, 1.0, Some(tinted));
+  let through = |scene: &Scene| {
+    let ray = Ray { origin: Point::zero(), direction: at(0.0) };
+    cast_ray(scene, &ray, &TraceState::default(), 0, &mut Rng::new(1))
+  };
+  let bare = through(&lit_wall_scene(vec![]));
+  let seen = through(&lit_wall_scene(vec![ball]));
+  assert!(bare.red > 0.0);
+  assert!((seen.red / bare.red - 0.25).abs() < 1e-3, "{:?} {:?}", seen, bare);
+  assert!((seen.green / bare.green - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_bumped_glass_refracts_by_its_geometry() {
+  use scene::{Absorption, Element, NormalMap, Sphere, SurfaceType, Texture, TextureFilter};
+  use rendering::{cast_ray, refracting_normal, TraceState, Ray};
+  use sampling::Rng;
+  use image::{DynamicImage, ImageBuffer, Rgba};
+  use point::Point;
+  use vector::Vector3;
+
+  // A shading normal that disagrees with the surface about which side a ray is on gives way.
+  let geometric = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+  let incident = Vector3 { x: 0.6, y: 0.0, z: -0.8 };
+  let leaning = Vector3 { x: -0.6, y: 0.0, z: 0.8 };
+  let overturned = Vector3 { x: -0.8, y: 0.0, z: -0.6 };
+  assert_eq!(refracting_normal(leaning, geometric, incident).x, -0.6);
+  assert_eq!(refracting_normal(overturned, geometric, incident).x, 0.0);
+  // Rays leave from the side of the surface they set off towards.
+  let ray = Ray { origin: Point::zero(), direction: incident }.offset_from(Point::zero(),
+                                                                           geometric,
+                                                                           0.01);
+  assert_eq!(ray.origin.z, -0.01);
+
+  // A glass ball with the same index as the air around it doesn't bend light, however steep
+  // its normal map, so a lit wall seen through it only loses the red its chord absorbs.
+  let tilted = DynamicImage::ImageRgba8(ImageBuffer::from_fn(4, 4, |_, _| {
+    Rgba { data: [218, 128, 218, 255] }
+  }));
+  let mut material = diffuse_material(1.0, 1.0, 1.0);
+  material.surface = SurfaceType::Refractive {
+    index: 1.0,
+    transparency: 1.0,
+    roughness: 0.0,
+    absorption: Some(Absorption {
+      color: Color { red: 0.5, green: 1.0, blue: 1.0 },
+      density: 1.0,
+    }),
+    dispersion: None,
+  };
+  material.normal_map = Some(NormalMap {
+    texture: Texture::new(&tilted, true, TextureFilter::Nearest),
+    strength: 20.0,
+  });
+  let ball = Element::Sphere(Sphere {
+    center: Point { x: 0.0, y: 0.0, z: -3.0 },
+    radius: 1.0,
+    transform: None,
+    material,
+  });
+  let bare = lit_wall_scene(vec![]);
+  let bumped = lit_wall_scene(vec![ball]);
+  for step in -9..10 {
+    let offset = step as f64 * 0.1;
+    let ray = Ray {
+      origin: Point { x: offset, y: 0.0, z: 0.0 },
+      direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+    };
+    let through = |scene| cast_ray(scene, &ray, &TraceState::default(), 0, &mut Rng::new(1));
+    let (seen, unobstructed) = (through(&bumped), through(&bare));
+    let chord = 2.0 * (1.0 - offset * offset).sqrt();
+    let expected = 0.5f64.powf(chord) as f32;
+    assert!((seen.red / unobstructed.red - expected).abs() < 1e-3,
+            "{}: {:?} {:?}",
+            offset,
+            seen,
+            unobstructed);
+    assert!((seen.green / unobstructed.green - 1.0).abs() < 1e-3);
+  }
+}
+
+#[test]
+fn test_dispersion_splits_light() {
+  use scene::{Camera, Coloration, DirectionalLight, Dispersion, Element, Gradient, Light, Plane,
+              Sphere, SurfaceType};
+  use point::Point;
+  use vector::Vector3;
+
+  // Both models bend blue more than red; Cauchy's is anchored to the index at the d line,
+  // and the Sellmeier coefficients for BK7 glass give its catalog index there.
+  let cauchy = Dispersion::Cauchy { b: 0.0042, c: 0.0 };
+  assert!((cauchy.index_at(1.5, 587.6) - 1.5).abs() < 1e-6);
+  assert!(cauchy.index_at(1.5, 450.0) > cauchy.index_at(1.5, 650.0));
+  let bk7 = Dispersion::Sellmeier {
+    b: [1.039_612, 0.231_792_34, 1.010_469_4],
+    c: [0.006_000_699, 0.020_017_914, 103.560_65],
+  };
+  assert!((bk7.index_at(0.0, 587.6) - 1.5168).abs() < 1e-4);
+  assert!(bk7.index_at(0.0, 450.0) > bk7.index_at(0.0, 650.0));
+
+  let ball = |dispersion: Option<Dispersion>| {
+    let mut material = diffuse_material(1.0, 1.0, 1.0);
+    material.surface = SurfaceType::Refractive {
+      index: 1.5,
+      transparency: 1.0,
+      roughness: 0.0,
+      absorption: None,
+      dispersion,
+    };
+    Element::Sphere(Sphere {
+      center: Point { x: 0.0, y: 0.0, z: -3.0 },
+      radius: 1.0,
+      transform: None,
+      material,
+    })
+  };
+  // Behind the ball, a wall that brightens evenly towards +x, lit evenly from above.
+  let wall = || {
+    let mut material = diffuse_material(1.0, 1.0, 1.0);
+    material.coloration = Coloration::Gradient(Gradient {
+      from: Color { red: 0.0, green: 0.0, blue: 0.0 },
+      to: Color { red: 1.0, green: 1.0, blue: 1.0 },
+      start: Point { x: -3.0, y: 0.0, z: -6.0 },
+      end: Point { x: 3.0, y: 0.0, z: -6.0 },
+      shape: Default::default(),
+      space: Default::default(),
+    });
+    Element::Plane(Plane {
+      origin: Point { x: 0.0, y: 0.0, z: -6.0 },
+      normal: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+      transform: None,
+      material,
+    })
+  };
+  let scene = |dispersion: Option<Dispersion>| {
+    let light = Light::Directional(DirectionalLight {
+      direction: Vector3 { x: 0.0, y: -1.0, z: -1.0 }.normalize(),
+      color: Color { red: 1.0, green: 1.0, blue: 1.0 },
+      intensity: 3.0,
+      spectrum: None,
+    });
+    Scene {
+      camera: Camera {
+        position: Point::zero(),
+        look_at: Point { x: 0.0, y: 0.0, z: -1.0 },
+        up: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+        fov: 90.0,
+      },
+      ..test_scene(vec![ball(dispersion), wall()], vec![light])
+    }
+  };
+
+  // The ball focuses light in front of the wall, so rays through its right side cross over
+  // and land on the darker left. Blue bends more and lands further left, so near the right
+  // edge of the ball's image, blue comes out darker than red.
+  let (x, y) = (19, 12);
+  let plain = render_pixel(&scene(None), x, y);
+  assert!(plain.red > 0.0);
+  assert_eq!(plain.red, plain.blue);
+  let strong = Dispersion::Cauchy { b: 0.05, c: 0.0 };
+  let split = render_pixel(&scene(Some(strong)), x, y);
+  assert!(split.blue < split.green && split.green < split.red, "{:?}", split);
+  assert!(split.blue < plain.blue && split.red > plain.red, "{:?} {:?}", split, plain);
+  // Splitting light that doesn't actually disperse changes nothing.
+  let flat = Dispersion::Cauchy { b: 0.0, c: 0.0 };
+  let unsplit = render_pixel(&scene(Some(flat)), x, y);
+  assert!((unsplit.red - plain.red).abs() < 1e-6 && (unsplit.blue - plain.blue).abs() < 1e-6);
+  assert!(render(&scene(Some(flat))).raw_pixels() == render(&scene(None)).raw_pixels());
+}
+
+#[test]
+fn test_spectral_rendering() {
+  use scene::{Element, Light, Plane, SphericalLight};
+  use spectrum::{cie_xyz, Spectrum, Wavelengths, MAX_WAVELENGTH, MIN_WAVELENGTH};
+  use rendering::WHITE;
+  use point::Point;
+  use vector::Vector3;
+
+  // The luminance curve peaks near 555nm, where the eye is most sensitive.
+  assert!(cie_xyz(555.0)[1] > 0.99 && cie_xyz(450.0)[1] < 0.05);
+
+  // A sample's wavelengths are a third of the range apart, wrapping around at the end.
+  let Wavelengths(sampled) = Wavelengths::sample(0.9);
+  let third = (MAX_WAVELENGTH - MIN_WAVELENGTH) / 3.0;
+  assert!((sampled[0] - (MIN_WAVELENGTH + 0.9 * 3.0 * third)).abs() < 1e-3);
+  assert!((sampled[2] - sampled[1] - third).abs() < 1e-3);
+  assert!(sampled.iter().all(|&w| (MIN_WAVELENGTH..MAX_WAVELENGTH).contains(&w)));
+
+  // Averaged over the range, a color lit by white light comes back about as it went in.
+  let round_trip = |color: Color| {
+    let n = 300;
+    (0..n).fold(BLACK, |total, i| {
+      let wavelengths = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+      let reflected = wavelengths.upsample(color) * wavelengths.white();
+      total + wavelengths.to_rgb(reflected) * (n as f32).recip()
+    })
+  };
+  let white = round_trip(WHITE);
+  assert!((white.red - 1.0).abs() < 1e-3 && (white.blue - 1.0).abs() < 1e-3, "{:?}", white);
+  let orange = round_trip(Color { red: 0.8, green: 0.5, blue: 0.2 });
+  assert!((orange.red - 0.8).abs() < 0.05 && (orange.green - 0.5).abs() < 0.05 &&
+          (orange.blue - 0.2).abs() < 0.05,
+          "{:?}",
+          orange);
+
+  // Blackbodies are as bright as white. At the temperature of daylight they look white,
+  // cooler ones look orange and hotter ones blue.
+  let blackbody = |temperature: f32| Spectrum::blackbody(temperature).unwrap().rgb();
+  let (warm, daylight, cold) = (blackbody(3000.0), blackbody(6504.0), blackbody(10000.0));
+  assert!((daylight.red - 1.0).abs() < 0.01 && (daylight.blue - 1.0).abs() < 0.01);
+  assert!(warm.red > warm.green && warm.green > warm.blue);
+  assert!(cold.blue > cold.red);
+  assert!((warm.luminance() - 1.0).abs() < 0.05 && (cold.luminance() - 1.0).abs() < 0.05);
+
+  let ramp = Spectrum::sampled(vec![400.0, 700.0], vec![0.0, 1.0]).unwrap();
+  assert!((ramp.value(550.0) / ramp.value(700.0) - 0.5).abs() < 1e-6);
+  assert_eq!(ramp.value(750.0), 0.0);
+  assert!(ramp.rgb().red > ramp.rgb().blue);
+
+  let parse = |yaml: &str| serde_yaml::from_str::<Spectrum>(yaml);
+  assert!(parse("Blackbody: { temperature: 2700 }").is_ok());
+  assert!(parse("Blackbody: { temperature: 0 }").is_err());
+  assert!(parse("Sampled: { wavelengths: [500, 400], values: [1, 1] }").is_err());
+  assert!(parse("Sampled: { wavelengths: [400, 500], values: [1] }").is_err());
+  assert!(parse("Sampled: { wavelengths: [800, 900], values: [1, 1] }").is_err());
+
+  // A gray floor renders the same either way, give or take color noise. Under a candle's
+  // blackbody it turns orange, and the RGB render agrees through the spectrum's color.
+  let scene = |spectral: bool, spectrum: Option<Spectrum>| {
+    let mut scene = test_scene(vec![Element::Plane(Plane {
+                                      origin: Point { x: 0.0, y: -1.0, z: 0.0 },
+                                      normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+                                      transform: None,
+                                      material: diffuse_material(0.5, 0.5, 0.5),
+                                    })],
+                               vec![Light::Spherical(SphericalLight {
+                                      position: Point { x: 0.0, y: 3.0, z: -3.0 },
+                                      color: WHITE,
+                                      intensity: 200.0,
+                                      radius: 0.0,
+                                      spectrum,
+                                    })]);
+    scene.spectral = spectral;
+    scene.samples_per_pixel = 16;
+    scene
+  };
+  let average = |scene: &Scene| {
+    let image = render_hdr(scene);
+    let pixels = image.pixels().count() as f32;
+    image.pixels().fold(BLACK, |total, pixel| {
+      total +
+      Color {
+        red: pixel.data[0],
+        green: pixel.data[1],
+        blue: pixel.data[2],
+      } * pixels.recip()
+    })
+  };
+  let rgb = average(&scene(false, None));
+  let spectral = average(&scene(true, None));
+  assert!(rgb.red > 0.0);
+  let channels = [(rgb.red, spectral.red), (rgb.green, spectral.green), (rgb.blue, spectral.blue)];
+  for &(a, b) in &channels {
+    assert!((b / a - 1.0).abs() < 0.02, "{:?} {:?}", rgb, spectral);
+  }
+  let candle = average(&scene(true, Spectrum::blackbody(1900.0)));
+  let candle_rgb = average(&scene(false, Spectrum::blackbody(1900.0)));
+  assert!(candle.red > 2.0 * candle.blue, "{:?}", candle);
+  assert!((candle.red / candle_rgb.red - 1.0).abs() < 0.02, "{:?} {:?}", candle, candle_rgb);
+}
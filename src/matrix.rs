@@ -0,0 +1,186 @@
+use std::ops::Mul;
+use serde::{Deserialize, Deserializer};
+use point::Point;
+use vector::Vector3;
+use quaternion::Quaternion;
+
+/// A 4x4 matrix of row vectors, applied to column vectors: points as (x, y, z, 1) and
+/// directions as (x, y, z, 0). Deserializes from a list of four rows.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+  pub rows: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+  pub fn identity() -> Matrix4 {
+    Matrix4::scaling(Vector3::from_one(1.0))
+  }
+
+  pub fn translation(offset: Vector3) -> Matrix4 {
+    Matrix4 {
+      rows: [[1.0, 0.0, 0.0, offset.x],
+             [0.0, 1.0, 0.0, offset.y],
+             [0.0, 0.0, 1.0, offset.z],
+             [0.0, 0.0, 0.0, 1.0]],
+    }
+  }
+
+  pub fn scaling(factors: Vector3) -> Matrix4 {
+    Matrix4 {
+      rows: [[factors.x, 0.0, 0.0, 0.0],
+             [0.0, factors.y, 0.0, 0.0],
+             [0.0, 0.0, factors.z, 0.0],
+             [0.0, 0.0, 0.0, 1.0]],
+    }
+  }
+
+  /// Rotates by the given angles, in degrees, around the x, y and z axes in that order.
+  pub fn rotation_euler(degrees: &Vector3) -> Matrix4 {
+    let (sx, cx) = degrees.x.to_radians().sin_cos();
+    let (sy, cy) = degrees.y.to_radians().sin_cos();
+    let (sz, cz) = degrees.z.to_radians().sin_cos();
+    let x = Matrix4::from_linear([[1.0, 0.0, 0.0], [0.0, cx, -sx], [0.0, sx, cx]]);
+    let y = Matrix4::from_linear([[cy, 0.0, sy], [0.0, 1.0, 0.0], [-sy, 0.0, cy]]);
+    let z = Matrix4::from_linear([[cz, -sz, 0.0], [sz, cz, 0.0], [0.0, 0.0, 1.0]]);
+    z * y * x
+  }
+
+  /// Rotates counterclockwise by `degrees` around `axis`, looking down the axis towards the
+  /// origin.
+  pub fn rotation_axis(axis: &Vector3, degrees: f64) -> Matrix4 {
+    Quaternion::from_axis_angle(axis, degrees).to_matrix()
+  }
+
+  /// Builds a matrix with the given linear part and no translation.
+  pub fn from_linear(linear: [[f64; 3]; 3]) -> Matrix4 {
+    let mut matrix = Matrix4::identity();
+    for (row, linear_row) in matrix.rows.iter_mut().zip(linear.iter()) {
+      row[..3].copy_from_slice(linear_row);
+    }
+    matrix
+  }
+
+  pub fn transpose(&self) -> Matrix4 {
+    let mut transposed = *self;
+    for (i, row) in transposed.rows.iter_mut().enumerate() {
+      for (j, value) in row.iter_mut().enumerate() {
+        *value = self.rows[j][i];
+      }
+    }
+    transposed
+  }
+
+  /// Inverts the matrix by Gauss-Jordan elimination, or returns None if it is singular.
+  pub fn inverse(&self) -> Option<Matrix4> {
+    let mut m = self.rows;
+    let mut inverse = Matrix4::identity().rows;
+    for column in 0..4 {
+      // Pivoting on the largest remaining entry keeps the elimination stable.
+      let pivot = (column..4)
+        .max_by(|&a, &b| m[a][column].abs().total_cmp(&m[b][column].abs()))
+        .unwrap();
+      if m[pivot][column].abs() < 1e-12 || !m[pivot][column].is_finite() {
+        return None;
+      }
+      m.swap(column, pivot);
+      inverse.swap(column, pivot);
+
+      let scale = m[column][column].recip();
+      for j in 0..4 {
+        m[column][j] *= scale;
+        inverse[column][j] *= scale;
+      }
+      for row in 0..4 {
+        if row == column {
+          continue;
+        }
+        let factor = m[row][column];
+        for j in 0..4 {
+          m[row][j] -= factor * m[column][j];
+          inverse[row][j] -= factor * inverse[column][j];
+        }
+      }
+    }
+    Some(Matrix4 { rows: inverse })
+  }
+
+  /// Whether the last row is (0, 0, 0, 1), so that the matrix maps points without
+  /// perspective.
+  pub fn is_affine(&self) -> bool {
+    self.rows[3] == [0.0, 0.0, 0.0, 1.0]
+  }
+
+  /// Maps a point, dividing through by w if the matrix is projective.
+  pub fn transform_point(&self, point: &Point) -> Point {
+    let r = &self.rows;
+    let apply = |i: usize| r[i][0] * point.x + r[i][1] * point.y + r[i][2] * point.z + r[i][3];
+    let w = apply(3);
+    let w = if w == 0.0 { 1.0 } else { w };
+    Point {
+      x: apply(0) / w,
+      y: apply(1) / w,
+      z: apply(2) / w,
+    }
+  }
+
+  /// Maps a direction, which translation leaves unchanged.
+  pub fn transform_vector(&self, vector: &Vector3) -> Vector3 {
+    let r = &self.rows;
+    Vector3 {
+      x: r[0][0] * vector.x + r[0][1] * vector.y + r[0][2] * vector.z,
+      y: r[1][0] * vector.x + r[1][1] * vector.y + r[1][2] * vector.z,
+      z: r[2][0] * vector.x + r[2][1] * vector.y + r[2][2] * vector.z,
+    }
+  }
+
+  /// The matrix that maps surface normals. Normals transform by the inverse transpose of
+  /// the linear part, which keeps them perpendicular to surfaces that are scaled unevenly;
+  /// its cofactor matrix points the same way and needs no inversion. Directions it maps
+  /// need renormalizing.
+  pub fn normal_matrix(&self) -> Matrix4 {
+    let r = &self.rows;
+    let cofactor = |row: usize, column: usize| {
+      let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+      let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+      r[r0][c0] * r[r1][c1] - r[r0][c1] * r[r1][c0]
+    };
+    let determinant = (0..3).map(|c| r[0][c] * cofactor(0, c)).sum::<f64>();
+    // A mirroring transform flips the cofactors' orientation; flip it back.
+    let sign = if determinant < 0.0 { -1.0 } else { 1.0 };
+    let mut linear = [[0.0; 3]; 3];
+    for (row, values) in linear.iter_mut().enumerate() {
+      for (column, value) in values.iter_mut().enumerate() {
+        *value = cofactor(row, column) * sign;
+      }
+    }
+    Matrix4::from_linear(linear)
+  }
+
+  /// Maps a surface normal by `normal_matrix`, returning a unit vector.
+  pub fn transform_normal(&self, normal: &Vector3) -> Vector3 {
+    self.normal_matrix().transform_vector(normal).normalize()
+  }
+}
+
+impl Mul for Matrix4 {
+  type Output = Matrix4;
+
+  fn mul(self, other: Matrix4) -> Matrix4 {
+    let mut product = [[0.0; 4]; 4];
+    for (i, row) in product.iter_mut().enumerate() {
+      for (j, value) in row.iter_mut().enumerate() {
+        *value = (0..4).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+      }
+    }
+    Matrix4 { rows: product }
+  }
+}
+
+impl Deserialize for Matrix4 {
+  fn deserialize<D>(deserializer: D) -> Result<Matrix4, D::Error>
+    where D: Deserializer
+  {
+    let rows = <[[f64; 4]; 4]>::deserialize(deserializer)?;
+    Ok(Matrix4 { rows })
+  }
+}
@@ -0,0 +1,85 @@
+//! Gradient noise for procedural textures.
+//!
+//! This is Perlin's improved noise, except that the gradient at each lattice point is chosen
+//! by hashing its coordinates rather than through a permutation table, so the pattern never
+//! repeats.
+
+use point::Point;
+
+/// The twelve edge directions of a cube, plus four repeats so a hash can pick one by masking.
+const GRADIENTS: [[f64; 3]; 16] = [[1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0],
+                                   [-1.0, -1.0, 0.0], [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0],
+                                   [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0], [0.0, 1.0, 1.0],
+                                   [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+                                   [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [0.0, -1.0, 1.0],
+                                   [0.0, -1.0, -1.0]];
+
+fn hash(x: i64, y: i64, z: i64) -> usize {
+  let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^
+              (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F) ^
+              (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+  h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+  h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+  (h ^ (h >> 31)) as usize
+}
+
+/// Perlin's quintic ease curve, which keeps the noise smooth across lattice cells.
+fn fade(t: f64) -> f64 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+  a + t * (b - a)
+}
+
+/// Smooth noise within [-1, 1], zero at every integer lattice point. Most values are much
+/// closer to zero than the bounds.
+pub fn perlin(point: &Point) -> f64 {
+  let cell = [point.x.floor(), point.y.floor(), point.z.floor()];
+  let (x, y, z) = (point.x - cell[0], point.y - cell[1], point.z - cell[2]);
+  let (cx, cy, cz) = (cell[0] as i64, cell[1] as i64, cell[2] as i64);
+
+  let corner = |dx: i64, dy: i64, dz: i64| {
+    let g = GRADIENTS[hash(cx + dx, cy + dy, cz + dz) & 15];
+    g[0] * (x - dx as f64) + g[1] * (y - dy as f64) + g[2] * (z - dz as f64)
+  };
+  let (u, v, w) = (fade(x), fade(y), fade(z));
+  lerp(w,
+       lerp(v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+       lerp(v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+}
+
+/// Fractal Brownian motion: `octaves` layers of noise, each at twice the frequency and
+/// `persistence` times the amplitude of the last, normalized back to [-1, 1].
+pub fn fbm(point: &Point, octaves: u32, persistence: f64) -> f64 {
+  layered(point, octaves, persistence, perlin)
+}
+
+/// Like `fbm`, but summing the absolute value of each layer, which gives sharp creases.
+/// The result is roughly in [0, 1].
+pub fn turbulence(point: &Point, octaves: u32, persistence: f64) -> f64 {
+  layered(point, octaves, persistence, |p| perlin(p).abs())
+}
+
+fn layered<F: Fn(&Point) -> f64>(point: &Point, octaves: u32, persistence: f64, layer: F) -> f64 {
+  let mut total = 0.0;
+  let mut amplitude = 1.0;
+  let mut norm = 0.0;
+  let mut frequency = 1.0;
+  for _ in 0..octaves.max(1) {
+    let scaled = Point {
+      x: point.x * frequency,
+      y: point.y * frequency,
+      z: point.z * frequency,
+    };
+    total += layer(&scaled) * amplitude;
+    norm += amplitude;
+    amplitude *= persistence;
+    frequency *= 2.0;
+  }
+  total / norm
+}
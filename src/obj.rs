@@ -0,0 +1,120 @@
+//! A minimal Wavefront OBJ reader: vertex positions, normals and texture coordinates, with
+//! polygonal faces triangulated as fans. Groups, smoothing and material statements are ignored.
+
+use point::Point;
+use vector::Vector3;
+use rendering::TextureCoords;
+use scene::{MeshData, MeshFace};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+pub fn load(path: &Path) -> Result<MeshData, String> {
+  let file = File::open(path).map_err(|e| e.to_string())?;
+  parse(BufReader::new(file))
+}
+
+pub fn parse<R: BufRead>(reader: R) -> Result<MeshData, String> {
+  let mut positions = Vec::new();
+  let mut normals = Vec::new();
+  let mut texture_coords = Vec::new();
+  let mut faces = Vec::new();
+
+  for (number, line) in reader.lines().enumerate() {
+    let line = line.map_err(|e| e.to_string())?;
+    let mut parts = line.split_whitespace();
+    let result = match parts.next() {
+      Some("v") => {
+        parse_floats(parts, 3).map(|v| positions.push(Point { x: v[0], y: v[1], z: v[2] }))
+      }
+      Some("vn") => {
+        parse_floats(parts, 3).map(|v| normals.push(Vector3 { x: v[0], y: v[1], z: v[2] }))
+      }
+      Some("vt") => {
+        // OBJ texture coordinates start at the bottom of the image; ours start at the top.
+        parse_floats(parts, 2).map(|v| {
+          texture_coords.push(TextureCoords {
+            x: v[0] as f32,
+            y: 1.0 - v[1] as f32,
+          })
+        })
+      }
+      Some("f") => {
+        let counts = (positions.len(), texture_coords.len(), normals.len());
+        parse_face(parts, counts).map(|polygon| {
+          for i in 1..polygon.len() - 1 {
+            let corners = [&polygon[0], &polygon[i], &polygon[i + 1]];
+            faces.push(MeshFace {
+              vertices: [corners[0].0, corners[1].0, corners[2].0],
+              texture_coords: match (corners[0].1, corners[1].1, corners[2].1) {
+                (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                _ => None,
+              },
+              normals: match (corners[0].2, corners[1].2, corners[2].2) {
+                (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                _ => None,
+              },
+            });
+          }
+        })
+      }
+      _ => Ok(()),
+    };
+    result.map_err(|e| format!("line {}: {}", number + 1, e))?;
+  }
+
+  Ok(MeshData::new(positions, normals, texture_coords, faces))
+}
+
+fn parse_floats<'a, I>(parts: I, count: usize) -> Result<Vec<f64>, String>
+  where I: Iterator<Item = &'a str>
+{
+  let values = parts.take(count)
+    .map(|p| p.parse::<f64>().map_err(|e| format!("invalid number '{}': {}", p, e)))
+    .collect::<Result<Vec<_>, _>>()?;
+  if values.len() < count {
+    return Err(format!("expected {} values", count));
+  }
+  Ok(values)
+}
+
+type Corner = (usize, Option<usize>, Option<usize>);
+
+fn parse_face<'a, I>(parts: I, counts: (usize, usize, usize)) -> Result<Vec<Corner>, String>
+  where I: Iterator<Item = &'a str>
+{
+  let corners = parts.map(|p| parse_corner(p, counts)).collect::<Result<Vec<_>, _>>()?;
+  if corners.len() < 3 {
+    return Err("a face needs at least three vertices".to_string());
+  }
+  Ok(corners)
+}
+
+/// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`, resolving one-based and negative indices.
+fn parse_corner(corner: &str, counts: (usize, usize, usize)) -> Result<Corner, String> {
+  let mut indices = corner.split('/');
+  let vertex = resolve_index(indices.next(), counts.0)?;
+  let texture = resolve_index(indices.next(), counts.1)?;
+  let normal = resolve_index(indices.next(), counts.2)?;
+  match vertex {
+    Some(v) => Ok((v, texture, normal)),
+    None => Err(format!("face corner '{}' has no vertex index", corner)),
+  }
+}
+
+fn resolve_index(index: Option<&str>, count: usize) -> Result<Option<usize>, String> {
+  let index = match index {
+    Some(i) if !i.is_empty() => i,
+    _ => return Ok(None),
+  };
+  let value = index.parse::<i64>().map_err(|e| format!("invalid index '{}': {}", index, e))?;
+  let resolved = if value < 0 {
+    count as i64 + value
+  } else {
+    value - 1
+  };
+  if resolved < 0 || resolved >= count as i64 {
+    return Err(format!("index {} is out of range", value));
+  }
+  Ok(Some(resolved as usize))
+}
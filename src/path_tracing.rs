@@ -0,0 +1,173 @@
+use scene::{Scene, Color, Intersection, SurfaceType};
+use rendering::{Ray, SurfaceNormals, TraceState, refracting_normal, shade_diffuse,
+                shade_principled, surface_color, surface_normals, refraction, fresnel, BLACK,
+                WHITE};
+use brdf::Principled;
+use sampling::{Rng, cosine_hemisphere};
+use point::Point;
+
+/// Paths shorter than this are never terminated by Russian roulette.
+const MIN_ROULETTE_DEPTH: u32 = 3;
+
+/// Estimates the light arriving along `ray` by following a single random path through the
+/// scene. Direct lighting is gathered at every diffuse vertex with shadow rays (next event
+/// estimation), and the path continues in one direction sampled from the surface.
+/// `max_recursion_depth` still caps path length as a safety net.
+pub fn trace_path<'a>(scene: &'a Scene,
+                      ray: &Ray,
+                      state: &TraceState<'a>,
+                      depth: u32,
+                      rng: &mut Rng)
+                      -> Color {
+    if depth >= scene.max_recursion_depth {
+        return BLACK;
+    }
+
+    match scene.trace(ray) {
+        Some(intersection) => {
+            let color = shade_path(scene, ray, state, &intersection, depth, rng);
+            state.attenuate(color, intersection.distance)
+        }
+        None => BLACK,
+    }
+}
+
+fn shade_path<'a>(scene: &'a Scene,
+                  ray: &Ray,
+                  state: &TraceState<'a>,
+                  intersection: &Intersection<'a>,
+                  depth: u32,
+                  rng: &mut Rng)
+                  -> Color {
+    let element = intersection.element;
+    let hit = ray.origin + (ray.direction * intersection.distance);
+    let normals = surface_normals(scene, intersection, hit, ray.direction);
+    let (geometric, normal) = (normals.geometric, normals.shading);
+    let bias = scene.shadow_bias;
+    let material = element.material();
+    let surface_color = surface_color(scene, state, intersection, hit, ray.direction);
+    let diffuse_reflectance = surface_color * (material.albedo * material.diffuse_weight());
+
+    match material.surface {
+        SurfaceType::Diffuse => {
+            shade_diffuse(scene, state, intersection, hit, normals, ray.direction, rng) +
+            indirect_diffuse(scene, hit, normals, diffuse_reflectance, state, depth, rng)
+        }
+        SurfaceType::Reflective { reflectivity, roughness } => {
+            // Direct light is cheap, so it is always gathered; the path itself either
+            // follows the reflection or bounces diffusely, in proportion to their weights.
+            let direct =
+                shade_diffuse(scene, state, intersection, hit, normals, ray.direction, rng) *
+                (1.0 - reflectivity);
+            if rng.next_f64() < reflectivity as f64 {
+                let reflection_ray =
+                    Ray::create_glossy_reflection(normal, ray.direction, hit, bias, roughness, rng)
+                        .offset_from(hit, geometric, bias);
+                direct + continue_path(scene, &reflection_ray, WHITE, state, depth, rng)
+            } else {
+                direct +
+                indirect_diffuse(scene, hit, normals, diffuse_reflectance, state, depth, rng)
+            }
+        }
+        SurfaceType::Refractive { transparency, roughness, dispersion, .. } => {
+            let highlights =
+                shade_diffuse(scene, state, intersection, hit, normals, ray.direction, rng);
+            let mut weight = surface_color * transparency;
+            let split;
+            let state = if dispersion.is_some() && state.channel().is_none() {
+                // Follow a single channel, picked at random; tripling its weight makes up for
+                // the two left behind.
+                let channel = ((rng.next_f64() * 3.0) as usize).min(2);
+                weight = weight.isolate(channel) * 3.0;
+                split = state.dispersed(channel);
+                &split
+            } else {
+                state
+            };
+            let (transmitted_state, index) = refraction(state, intersection, ray, geometric);
+            let incident = ray.direction;
+            let normal = refracting_normal(normal, geometric, incident);
+            let kr = fresnel(incident, normal, index);
+            let transmission_ray = if rng.next_f64() < kr {
+                None
+            } else {
+                Ray::create_glossy_transmission(normal, incident, hit, bias, index, roughness, rng)
+                    .map(|transmission| transmission.offset_from(hit, geometric, bias))
+            };
+            highlights +
+            match transmission_ray {
+                Some(next_ray) => {
+                    continue_path(scene, &next_ray, weight, &transmitted_state, depth, rng)
+                }
+                None => {
+                    let next_ray =
+                        Ray::create_glossy_reflection(normal, incident, hit, bias, roughness, rng)
+                            .offset_from(hit, geometric, bias);
+                    continue_path(scene, &next_ray, weight, state, depth, rng)
+                }
+            }
+        }
+        SurfaceType::Principled { metallic, roughness, specular } => {
+            let brdf = Principled::new(surface_color * material.albedo,
+                                       metallic,
+                                       roughness,
+                                       specular);
+            let direct =
+                shade_principled(scene, state, &brdf, hit, normals, ray.direction, rng);
+            match brdf.sample(&normal, &-ray.direction, rng) {
+                Some(sample) => {
+                    let bounce = Ray {
+                        origin: hit,
+                        direction: sample.direction,
+                    }
+                    .offset_from(hit, geometric, bias);
+                    direct + continue_path(scene, &bounce, sample.weight, state, depth, rng)
+                }
+                None => direct,
+            }
+        }
+    }
+}
+
+/// Follows a cosine-distributed bounce off a Lambertian surface. With that distribution the
+/// cosine term and the sampling density cancel, leaving only the surface's reflectance.
+fn indirect_diffuse<'a>(scene: &'a Scene,
+                        hit: Point,
+                        normals: SurfaceNormals,
+                        reflectance: Color,
+                        state: &TraceState<'a>,
+                        depth: u32,
+                        rng: &mut Rng)
+                        -> Color {
+    let bounce = Ray {
+        origin: hit,
+        direction: cosine_hemisphere(&normals.shading, rng),
+    };
+    // A tilted shading normal can send the bounce into the surface, where it finds no light.
+    if bounce.direction.dot(&normals.geometric) <= 0.0 {
+        return BLACK;
+    }
+    let bounce = bounce.offset_from(hit, normals.geometric, scene.shadow_bias);
+    continue_path(scene, &bounce, reflectance, state, depth, rng)
+}
+
+/// Traces the next segment of the path, scaled by `weight`. Past the first few bounces,
+/// paths are randomly terminated with a probability that grows as their weight shrinks, and
+/// survivors are boosted to compensate, which keeps the estimate unbiased.
+fn continue_path<'a>(scene: &'a Scene,
+                     ray: &Ray,
+                     weight: Color,
+                     state: &TraceState<'a>,
+                     depth: u32,
+                     rng: &mut Rng)
+                     -> Color {
+    let mut weight = weight;
+    if depth + 1 >= MIN_ROULETTE_DEPTH {
+        let survival = weight.red.max(weight.green).max(weight.blue).min(0.95);
+        if survival <= 0.0 || rng.next_f64() >= survival as f64 {
+            return BLACK;
+        }
+        weight = weight * survival.recip();
+    }
+    trace_path(scene, ray, state, depth + 1, rng) * weight
+}
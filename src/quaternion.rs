@@ -0,0 +1,129 @@
+use std::ops::{Add, Mul};
+use matrix::Matrix4;
+use vector::Vector3;
+
+/// A quaternion w + xi + yj + zk. Unit quaternions represent rotations.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct Quaternion {
+  pub w: f64,
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
+}
+
+impl Quaternion {
+  pub fn identity() -> Quaternion {
+    Quaternion { w: 1.0, x: 0.0, y: 0.0, z: 0.0 }
+  }
+
+  /// The rotation counterclockwise by `degrees` around `axis`, looking down the axis
+  /// towards the origin.
+  pub fn from_axis_angle(axis: &Vector3, degrees: f64) -> Quaternion {
+    let axis = axis.normalize();
+    let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+    Quaternion {
+      w: cos,
+      x: axis.x * sin,
+      y: axis.y * sin,
+      z: axis.z * sin,
+    }
+  }
+
+  pub fn dot(&self, other: &Quaternion) -> f64 {
+    self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+  }
+
+  pub fn length(&self) -> f64 {
+    self.dot(self).sqrt()
+  }
+
+  pub fn normalize(&self) -> Quaternion {
+    *self * self.length().recip()
+  }
+
+  pub fn conjugate(&self) -> Quaternion {
+    Quaternion {
+      w: self.w,
+      x: -self.x,
+      y: -self.y,
+      z: -self.z,
+    }
+  }
+
+  /// Rotates `v` by this unit quaternion.
+  pub fn rotate(&self, v: &Vector3) -> Vector3 {
+    let q = Vector3 { x: self.x, y: self.y, z: self.z };
+    let t = q.cross(v) * 2.0;
+    *v + t * self.w + q.cross(&t)
+  }
+
+  /// Spherical linear interpolation between two unit quaternions: rotates at a constant
+  /// rate from `self` at t = 0 to `other` at t = 1, the short way around.
+  pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+    let mut other = *other;
+    let mut cos = self.dot(&other);
+    // q and -q are the same rotation; pick the one on this side to take the short way.
+    if cos < 0.0 {
+      other = other * -1.0;
+      cos = -cos;
+    }
+    if cos > 0.9995 {
+      // Nearly parallel: the arc is too short for the sines below to be accurate.
+      return (*self * (1.0 - t) + other * t).normalize();
+    }
+    let angle = cos.acos();
+    let sin = angle.sin();
+    *self * (((1.0 - t) * angle).sin() / sin) + other * ((t * angle).sin() / sin)
+  }
+
+  /// The rotation matrix for this unit quaternion.
+  pub fn to_matrix(&self) -> Matrix4 {
+    let Quaternion { w, x, y, z } = *self;
+    let (xx, yy, zz) = (x * x, y * y, z * z);
+    let (xy, xz, yz) = (x * y, x * z, y * z);
+    let (wx, wy, wz) = (w * x, w * y, w * z);
+    Matrix4::from_linear([[1.0 - 2.0 * (yy + zz), 2.0 * (xy - wz), 2.0 * (xz + wy)],
+                          [2.0 * (xy + wz), 1.0 - 2.0 * (xx + zz), 2.0 * (yz - wx)],
+                          [2.0 * (xz - wy), 2.0 * (yz + wx), 1.0 - 2.0 * (xx + yy)]])
+  }
+}
+
+/// The Hamilton product: rotating by `self * other` applies `other` first.
+impl Mul for Quaternion {
+  type Output = Quaternion;
+
+  fn mul(self, other: Quaternion) -> Quaternion {
+    Quaternion {
+      w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+      x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+      y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+      z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+    }
+  }
+}
+
+impl Mul<f64> for Quaternion {
+  type Output = Quaternion;
+
+  fn mul(self, scale: f64) -> Quaternion {
+    Quaternion {
+      w: self.w * scale,
+      x: self.x * scale,
+      y: self.y * scale,
+      z: self.z * scale,
+    }
+  }
+}
+
+impl Add for Quaternion {
+  type Output = Quaternion;
+
+  fn add(self, other: Quaternion) -> Quaternion {
+    Quaternion {
+      w: self.w + other.w,
+      x: self.x + other.x,
+      y: self.y + other.y,
+      z: self.z + other.z,
+    }
+  }
+}
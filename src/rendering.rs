@@ -1,7 +1,15 @@
 use point::Point;
 use vector::Vector3;
-use scene::{Scene, Element, Sphere, Plane, Color, Intersection, SurfaceType};
+use scene::{Scene, Element, Sphere, Plane, Triangle, Mesh, Cuboid, Color, Intersection, SurfaceType,
+            SamplePattern, Integrator, Light, Absorption, Dispersion, Coloration, TextureFilter,
+            CHANNEL_WAVELENGTHS};
+use spectrum::Wavelengths;
+use sampling::{Rng, permute};
+use brdf::{self, Principled};
+use path_tracing;
+use bvh::BoundingBox;
 use std::f32;
+use std::ptr;
 
 #[derive(Debug)]
 pub struct Ray {
@@ -10,22 +18,24 @@ pub struct Ray {
 }
 
 impl Ray {
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
-        assert!(scene.width >= scene.height);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
+    /// Creates a ray through the given point on the image plane, measured in pixels
+    /// from the top-left corner (so the center of pixel (0, 0) is at (0.5, 0.5)).
+    pub fn create_prime(x: f64, y: f64, scene: &Scene) -> Ray {
+        let camera = &scene.camera;
+        let fov_adjustment = (camera.fov.to_radians() / 2.0).tan();
         let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x = ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) *
-                       fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+        let (scale_x, scale_y) = if aspect_ratio >= 1.0 {
+            (aspect_ratio, 1.0)
+        } else {
+            (1.0, 1.0 / aspect_ratio)
+        };
+        let sensor_x = ((x / scene.width as f64) * 2.0 - 1.0) * scale_x * fov_adjustment;
+        let sensor_y = (1.0 - (y / scene.height as f64) * 2.0) * scale_y * fov_adjustment;
 
+        let (right, up, forward) = camera.basis();
         Ray {
-            origin: Point::zero(),
-            direction: Vector3 {
-                    x: sensor_x,
-                    y: sensor_y,
-                    z: -1.0,
-                }
-                .normalize(),
+            origin: camera.position,
+            direction: (right * sensor_x + up * sensor_y + forward).normalize(),
         }
     }
 
@@ -71,45 +81,160 @@ impl Ray {
                  })
         }
     }
+
+    /// Moves the ray's origin `bias` off the surface at `hit`, along the geometric normal and
+    /// to the side the ray sets off towards. Offsetting along a bumped or normal-mapped
+    /// shading normal instead can leave the origin on the wrong side of the surface.
+    pub fn offset_from(self, hit: Point, geometric_normal: Vector3, bias: f64) -> Ray {
+        let bias = if self.direction.dot(&geometric_normal) < 0.0 { -bias } else { bias };
+        Ray {
+            origin: hit + (geometric_normal * bias),
+            direction: self.direction,
+        }
+    }
+
+    /// Like `create_reflection`, but reflects off a microfacet normal drawn from a GGX lobe
+    /// of the given roughness, which blurs the reflection. Falls back to the mirror direction
+    /// when the lobe would send the ray into the surface.
+    pub fn create_glossy_reflection(normal: Vector3,
+                                    incident: Vector3,
+                                    intersection: Point,
+                                    bias: f64,
+                                    roughness: f32,
+                                    rng: &mut Rng)
+                                    -> Ray {
+        let mirror = Ray::create_reflection(normal, incident, intersection, bias);
+        if roughness <= 0.0 {
+            return mirror;
+        }
+        let facing = if incident.dot(&normal) < 0.0 { normal } else { -normal };
+        let microfacet = brdf::sample_ggx_normal(&facing, brdf::roughness_to_alpha(roughness), rng);
+        let direction = incident - (2.0 * incident.dot(&microfacet) * microfacet);
+        if direction.dot(&facing) <= 0.0 {
+            return mirror;
+        }
+        Ray {
+            origin: mirror.origin,
+            direction,
+        }
+    }
+
+    /// Like `create_transmission`, but refracts through a microfacet normal drawn from a GGX
+    /// lobe of the given roughness, which frosts the surface. Falls back to the smooth
+    /// direction when the microfacet would reflect the ray or bend it back out.
+    pub fn create_glossy_transmission(normal: Vector3,
+                                      incident: Vector3,
+                                      intersection: Point,
+                                      bias: f64,
+                                      index: f32,
+                                      roughness: f32,
+                                      rng: &mut Rng)
+                                      -> Option<Ray> {
+        if roughness <= 0.0 {
+            return Ray::create_transmission(normal, incident, intersection, bias, index);
+        }
+        let microfacet = brdf::sample_ggx_normal(&normal, brdf::roughness_to_alpha(roughness), rng);
+        let same_side = |ray: &Ray| ray.direction.dot(&normal) * incident.dot(&normal) > 0.0;
+        match Ray::create_transmission(microfacet, incident, intersection, bias, index) {
+            Some(ray) if same_side(&ray) => Some(ray),
+            _ => Ray::create_transmission(normal, incident, intersection, bias, index),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
 }
 
+/// Where a ray hit an element: the distance along the ray and, for elements
+/// made up of several faces (such as meshes), which face was hit.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub distance: f64,
+    pub face: usize,
+}
+
+impl Hit {
+    pub fn at(distance: f64) -> Hit {
+        Hit {
+            distance,
+            face: 0,
+        }
+    }
+}
+
 pub trait Intersectable {
-    fn intersect(&self, ray: &Ray) -> Option<f64>;
+    fn intersect(&self, ray: &Ray) -> Option<Hit>;
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3;
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
+    fn surface_normal(&self, hit_point: &Point, face: usize) -> Vector3;
+    fn texture_coords(&self, hit_point: &Point, face: usize) -> TextureCoords;
+
+    /// Returns None for unbounded elements such as planes.
+    fn bounding_box(&self) -> Option<BoundingBox>;
 }
 
-impl Intersectable for Element {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+impl Element {
+    /// The element's shape, in object space.
+    fn shape(&self) -> &dyn Intersectable {
         match *self {
-            Element::Sphere(ref s) => s.intersect(ray),
-            Element::Plane(ref p) => p.intersect(ray),
+            Element::Sphere(ref s) => s,
+            Element::Plane(ref p) => p,
+            Element::Triangle(ref t) => t,
+            Element::Mesh(ref m) => m,
+            Element::Box(ref b) => b,
         }
     }
+}
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
-        match *self {
-            Element::Sphere(ref s) => s.surface_normal(hit_point),
-            Element::Plane(ref p) => p.surface_normal(hit_point),
+/// Elements with a transform are intersected by carrying the ray into object space. Hit
+/// points go the same way, and normals come back out.
+impl Intersectable for Element {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        match self.transform() {
+            None => self.shape().intersect(ray),
+            Some(transform) => {
+                let (local_ray, stretch) = transform.ray_to_local(ray);
+                self.shape().intersect(&local_ray).map(|hit| {
+                    Hit {
+                        distance: hit.distance / stretch,
+                        face: hit.face,
+                    }
+                })
+            }
         }
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
-        match *self {
-            Element::Sphere(ref s) => s.texture_coords(hit_point),
-            Element::Plane(ref p) => p.texture_coords(hit_point),
+    fn surface_normal(&self, hit_point: &Point, face: usize) -> Vector3 {
+        match self.transform() {
+            None => self.shape().surface_normal(hit_point, face),
+            Some(transform) => {
+                let local = transform.point_to_local(hit_point);
+                transform.normal_to_world(&self.shape().surface_normal(&local, face))
+            }
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, face: usize) -> TextureCoords {
+        match self.transform() {
+            None => self.shape().texture_coords(hit_point, face),
+            Some(transform) => {
+                self.shape().texture_coords(&transform.point_to_local(hit_point), face)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let bounds = self.shape().bounding_box();
+        match self.transform() {
+            None => bounds,
+            Some(transform) => bounds.map(|b| transform.bounds_to_world(&b)),
         }
     }
 }
 impl Intersectable for Sphere {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
         let l: Vector3 = self.center - ray.origin;
         let adj = l.dot(&ray.direction);
         let d2 = l.dot(&l) - (adj * adj);
@@ -124,46 +249,54 @@ impl Intersectable for Sphere {
         if t0 < 0.0 && t1 < 0.0 {
             None
         } else if t0 < 0.0 {
-            Some(t1)
+            Some(Hit::at(t1))
         } else if t1 < 0.0 {
-            Some(t0)
+            Some(Hit::at(t0))
         } else {
             let distance = if t0 < t1 { t0 } else { t1 };
-            Some(distance)
+            Some(Hit::at(distance))
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    fn surface_normal(&self, hit_point: &Point, _: usize) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn texture_coords(&self, hit_point: &Point, _: usize) -> TextureCoords {
         let hit_vec = *hit_point - self.center;
         TextureCoords {
             x: (1.0 + (hit_vec.z.atan2(hit_vec.x) as f32) / f32::consts::PI) * 0.5,
-            y: (hit_vec.y / self.radius).acos() as f32 / f32::consts::PI,
+            y: (hit_vec.y / self.radius).clamp(-1.0, 1.0).acos() as f32 / f32::consts::PI,
         }
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let extent = Vector3::from_one(self.radius);
+        Some(BoundingBox {
+                 min: self.center - extent,
+                 max: self.center + extent,
+             })
+    }
 }
 impl Intersectable for Plane {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
         let normal = &self.normal;
         let denom = normal.dot(&ray.direction);
         if denom > 1e-6 {
             let v = self.origin - ray.origin;
             let distance = v.dot(&normal) / denom;
             if distance >= 0.0 {
-                return Some(distance);
+                return Some(Hit::at(distance));
             }
         }
         None
     }
 
-    fn surface_normal(&self, _: &Point) -> Vector3 {
+    fn surface_normal(&self, _: &Point, _: usize) -> Vector3 {
         -self.normal
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn texture_coords(&self, hit_point: &Point, _: usize) -> TextureCoords {
         let mut x_axis = self.normal
             .cross(&Vector3 {
                         x: 0.0,
@@ -186,91 +319,869 @@ impl Intersectable for Plane {
             y: hit_vec.dot(&y_axis) as f32,
         }
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+}
+
+/// Moller-Trumbore ray/triangle intersection. Triangles are double-sided: this finds hits
+/// from either side, and `facing_normal` shades them from the side they're seen from.
+fn intersect_triangle(ray: &Ray, vertices: &[Point; 3]) -> Option<f64> {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let p = ray.direction.cross(&edge2);
+    let det = edge1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = det.recip();
+    let t = ray.origin - vertices[0];
+    let u = t.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = t.cross(&edge1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(&q) * inv_det;
+    if distance >= 0.0 { Some(distance) } else { None }
+}
+
+/// Returns the barycentric weights of `point` with respect to the triangle's vertices.
+fn barycentric(point: &Point, vertices: &[Point; 3]) -> [f64; 3] {
+    let v0 = vertices[1] - vertices[0];
+    let v1 = vertices[2] - vertices[0];
+    let v2 = *point - vertices[0];
+    let d00 = v0.dot(&v0);
+    let d01 = v0.dot(&v1);
+    let d11 = v1.dot(&v1);
+    let d20 = v2.dot(&v0);
+    let d21 = v2.dot(&v1);
+    let denom = d00 * d11 - d01 * d01;
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+    [1.0 - v - w, v, w]
+}
+
+fn triangle_normal(point: &Point,
+                   vertices: &[Point; 3],
+                   normals: Option<&[Vector3; 3]>)
+                   -> Vector3 {
+    match normals {
+        Some(n) => {
+            let b = barycentric(point, vertices);
+            (n[0] * b[0] + n[1] * b[1] + n[2] * b[2]).normalize()
+        }
+        None => (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).normalize(),
+    }
+}
+
+fn triangle_texture_coords(point: &Point,
+                           vertices: &[Point; 3],
+                           coords: Option<&[TextureCoords; 3]>)
+                           -> TextureCoords {
+    let b = barycentric(point, vertices);
+    match coords {
+        Some(c) => {
+            TextureCoords {
+                x: c[0].x * b[0] as f32 + c[1].x * b[1] as f32 + c[2].x * b[2] as f32,
+                y: c[0].y * b[0] as f32 + c[1].y * b[1] as f32 + c[2].y * b[2] as f32,
+            }
+        }
+        None => {
+            TextureCoords {
+                x: b[1] as f32,
+                y: b[2] as f32,
+            }
+        }
+    }
+}
+
+fn triangle_bounds(vertices: &[Point; 3]) -> BoundingBox {
+    vertices.iter().fold(BoundingBox::empty(), |b, v| b.grow(v))
 }
 
-const BLACK: Color = Color {
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        intersect_triangle(ray, &self.vertices).map(Hit::at)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: usize) -> Vector3 {
+        triangle_normal(hit_point, &self.vertices, self.normals.as_ref())
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: usize) -> TextureCoords {
+        triangle_texture_coords(hit_point, &self.vertices, self.texture_coords.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(triangle_bounds(&self.vertices))
+    }
+}
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let geometry = &self.geometry;
+        geometry.bvh
+            .closest(ray, |face| {
+                intersect_triangle(ray, &geometry.face_vertices(face))
+                    .map(|distance| Hit { distance, face })
+            })
+            .map(|(_, hit)| hit)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, face: usize) -> Vector3 {
+        let geometry = &self.geometry;
+        triangle_normal(hit_point,
+                        &geometry.face_vertices(face),
+                        geometry.face_normals(face).as_ref())
+    }
+
+    fn texture_coords(&self, hit_point: &Point, face: usize) -> TextureCoords {
+        let geometry = &self.geometry;
+        triangle_texture_coords(hit_point,
+                                &geometry.face_vertices(face),
+                                geometry.face_texture_coords(face).as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let geometry = &self.geometry;
+        Some((0..geometry.faces.len())
+                 .map(|face| triangle_bounds(&geometry.face_vertices(face)))
+                 .fold(BoundingBox::empty(), |a, b| a.union(&b)))
+    }
+}
+
+fn axis(v: &Vector3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+// Box faces are numbered 2 * axis, plus one for the face on the positive side.
+impl Cuboid {
+    fn center(&self) -> Point {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    fn half_extent(&self) -> Vector3 {
+        (self.max - self.min) * 0.5
+    }
+}
+impl Intersectable for Cuboid {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let origin = ray.origin - self.center();
+        let half = self.half_extent();
+
+        let (mut t_near, mut near_face) = (f64::NEG_INFINITY, 0);
+        let (mut t_far, mut far_face) = (f64::INFINITY, 0);
+        for a in 0..3 {
+            let (o, d, h) = (axis(&origin, a), axis(&ray.direction, a), axis(&half, a));
+            if d.abs() < 1e-12 {
+                if o.abs() > h {
+                    return None;
+                }
+                continue;
+            }
+            let t_neg = (-h - o) / d;
+            let t_pos = (h - o) / d;
+            let (t0, face0, t1, face1) = if t_neg < t_pos {
+                (t_neg, 2 * a, t_pos, 2 * a + 1)
+            } else {
+                (t_pos, 2 * a + 1, t_neg, 2 * a)
+            };
+            if t0 > t_near {
+                t_near = t0;
+                near_face = face0;
+            }
+            if t1 < t_far {
+                t_far = t1;
+                far_face = face1;
+            }
+        }
+
+        if t_near > t_far || t_far < 0.0 {
+            None
+        } else if t_near >= 0.0 {
+            Some(Hit {
+                     distance: t_near,
+                     face: near_face,
+                 })
+        } else {
+            Some(Hit {
+                     distance: t_far,
+                     face: far_face,
+                 })
+        }
+    }
+
+    fn surface_normal(&self, _: &Point, face: usize) -> Vector3 {
+        let sign = if face % 2 == 1 { 1.0 } else { -1.0 };
+        match face / 2 {
+            0 => Vector3 { x: sign, y: 0.0, z: 0.0 },
+            1 => Vector3 { x: 0.0, y: sign, z: 0.0 },
+            _ => Vector3 { x: 0.0, y: 0.0, z: sign },
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, face: usize) -> TextureCoords {
+        let local = *hit_point - self.center();
+        let half = self.half_extent();
+        // Each face is mapped onto the whole texture, with v pointing down the face.
+        let (u_axis, v_axis) = match face / 2 {
+            0 => (2, 1),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        let coord = |a: usize| (axis(&local, a) + axis(&half, a)) / (2.0 * axis(&half, a));
+        TextureCoords {
+            x: coord(u_axis) as f32,
+            y: (1.0 - coord(v_axis)) as f32,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox {
+                 min: self.min,
+                 max: self.max,
+             })
+    }
+}
+
+pub const BLACK: Color = Color {
     red: 0.0,
     green: 0.0,
     blue: 0.0,
 };
 
-fn shade_diffuse(scene: &Scene,
-                 element: &Element,
+pub const WHITE: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+};
+
+/// The angle between the rays through neighbouring pixels.
+fn pixel_spread(scene: &Scene) -> f64 {
+    let fov_adjustment = (scene.camera.fov.to_radians() / 2.0).tan();
+    2.0 * fov_adjustment / scene.width.min(scene.height).max(1) as f64
+}
+
+/// How far to step across a surface when measuring how its texture coordinates change.
+const GRADIENT_STEP: f64 = 1e-4;
+
+/// How texture coordinates change across a surface, per unit of distance along two
+/// perpendicular tangent directions. Each rate is measured by stepping a short way both
+/// ways and keeping the smaller change, so that seams where texture coordinates wrap around
+/// don't look like steep slopes.
+struct TextureGradient {
+    normal: Vector3,
+    tangent: Vector3,
+    bitangent: Vector3,
+    along_tangent: (f64, f64),
+    along_bitangent: (f64, f64),
+}
+impl TextureGradient {
+    fn new(intersection: &Intersection, hit_point: Point) -> TextureGradient {
+        let element = intersection.element;
+        let face = intersection.face;
+        let normal = element.surface_normal(&hit_point, face);
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let center = element.texture_coords(&hit_point, face);
+        let rate = |direction: Vector3| {
+            let change = |point: Point| {
+                let coords = element.texture_coords(&point, face);
+                ((coords.x - center.x) as f64, (coords.y - center.y) as f64)
+            };
+            let forward = change(hit_point + direction * GRADIENT_STEP);
+            let backward = change(hit_point - direction * GRADIENT_STEP);
+            let (du, dv) = if forward.0.hypot(forward.1) <= backward.0.hypot(backward.1) {
+                forward
+            } else {
+                (-backward.0, -backward.1)
+            };
+            (du / GRADIENT_STEP, dv / GRADIENT_STEP)
+        };
+        TextureGradient {
+            normal,
+            tangent,
+            bitangent,
+            along_tangent: rate(tangent),
+            along_bitangent: rate(bitangent),
+        }
+    }
+
+    /// The change in texture coordinates per unit of distance along `direction`, which
+    /// should lie in the tangent plane.
+    fn along(&self, direction: Vector3) -> (f64, f64) {
+        let (a, b) = (direction.dot(&self.tangent), direction.dot(&self.bitangent));
+        (a * self.along_tangent.0 + b * self.along_bitangent.0,
+         a * self.along_tangent.1 + b * self.along_bitangent.1)
+    }
+
+    /// The gradients of the two texture coordinates: for each, the direction in which it
+    /// grows fastest, scaled by how fast it grows.
+    fn gradients(&self) -> (Vector3, Vector3) {
+        (self.tangent * self.along_tangent.0 + self.bitangent * self.along_bitangent.0,
+         self.tangent * self.along_tangent.1 + self.bitangent * self.along_bitangent.1)
+    }
+
+    /// How the surface moves as each texture coordinate grows by one, or None where the
+    /// texture coordinates don't vary independently.
+    fn axes(&self) -> Option<(Vector3, Vector3)> {
+        let (tu, tv) = self.along_tangent;
+        let (bu, bv) = self.along_bitangent;
+        let determinant = tu * bv - bu * tv;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        Some(((self.tangent * bv - self.bitangent * tv) * determinant.recip(),
+              (self.bitangent * tu - self.tangent * bu) * determinant.recip()))
+    }
+
+    /// The width, in texture coordinates, of the area a pixel covers: its width at the hit
+    /// distance, stretched by the viewing angle along the direction the view slants in.
+    fn footprint(&self, scene: &Scene, distance: f64, view_direction: Vector3) -> f32 {
+        let width = pixel_spread(scene) * distance;
+        let cos = view_direction.dot(&self.normal).abs().max(0.05);
+        let along_view = view_direction - self.normal * view_direction.dot(&self.normal);
+        let major = if along_view.length() > 1e-9 {
+            along_view.normalize()
+        } else {
+            self.tangent
+        };
+        let minor = self.normal.cross(&major);
+        let extent = |direction: Vector3, length: f64| {
+            let (du, dv) = self.along(direction);
+            (du.hypot(dv) * length) as f32
+        };
+        extent(major, width / cos).max(extent(minor, width))
+    }
+}
+
+/// Returns the surface color at `intersection`, seen along `view_direction` by a ray
+/// carrying `state`.
+pub fn surface_color(scene: &Scene,
+                     state: &TraceState,
+                     intersection: &Intersection,
+                     hit_point: Point,
+                     view_direction: Vector3)
+                     -> Color {
+    let element = intersection.element;
+    let coloration = &element.material().coloration;
+    let texture_coords = element.texture_coords(&hit_point, intersection.face);
+    // Only textures filtered by their footprint need to know how the coordinates change.
+    let footprint = match *coloration {
+        Coloration::Texture(ref texture) if texture.filter == TextureFilter::Trilinear => {
+            TextureGradient::new(intersection, hit_point)
+                .footprint(scene, intersection.distance, view_direction)
+        }
+        _ => 0.0,
+    };
+    state.reflectance(coloration.color(&texture_coords, &hit_point, footprint))
+}
+
+/// Returns the surface normal at `intersection`, seen along `view_direction`. Triangles,
+/// meshes and boxes are double-sided, so their normal is turned to face the ray, which lights
+/// a box from inside as a room. Refractive materials are the exception: there the outward
+/// normal tells rays entering an element from rays leaving it.
+pub fn facing_normal(intersection: &Intersection,
+                     hit_point: Point,
+                     view_direction: Vector3)
+                     -> Vector3 {
+    let element = intersection.element;
+    let normal = element.surface_normal(&hit_point, intersection.face);
+    let double_sided = match *element {
+        Element::Triangle(_) | Element::Mesh(_) | Element::Box(_) => {
+            !matches!(element.material().surface, SurfaceType::Refractive { .. })
+        }
+        _ => false,
+    };
+    if double_sided && normal.dot(&view_direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+/// The normals where a ray meets a surface: the geometric one, which tells the two sides of
+/// the surface apart and so decides where rays leaving it start, and the one to shade with,
+/// which normal and bump maps tilt.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceNormals {
+    pub geometric: Vector3,
+    pub shading: Vector3,
+}
+
+/// Returns the normals at `intersection`, seen along `view_direction`.
+pub fn surface_normals(scene: &Scene,
+                       intersection: &Intersection,
+                       hit_point: Point,
+                       view_direction: Vector3)
+                       -> SurfaceNormals {
+    let geometric = facing_normal(intersection, hit_point, view_direction);
+    SurfaceNormals {
+        geometric,
+        shading: shading_normal(scene, intersection, hit_point, geometric, view_direction),
+    }
+}
+
+/// Returns the normal to shade `intersection` with: `surface_normal`, tilted by the
+/// material's normal map and bump map, if it has them.
+pub fn shading_normal(scene: &Scene,
+                      intersection: &Intersection,
+                      hit_point: Point,
+                      surface_normal: Vector3,
+                      view_direction: Vector3)
+                      -> Vector3 {
+    let material = intersection.element.material();
+    if material.normal_map.is_none() && material.bump_map.is_none() {
+        return surface_normal;
+    }
+    let gradient = TextureGradient::new(intersection, hit_point);
+    let footprint = gradient.footprint(scene, intersection.distance, view_direction);
+    let texture_coords = intersection.element.texture_coords(&hit_point, intersection.face);
+    let mut normal = surface_normal;
+
+    if let (Some(map), Some((along_u, along_v))) = (material.normal_map.as_ref(), gradient.axes()) {
+        // Texture coordinates grow downwards through the image, so "up" in the map is -v.
+        let tangent = (along_u - normal * normal.dot(&along_u)).normalize();
+        let up = -along_v;
+        let up = (up - normal * normal.dot(&up) - tangent * tangent.dot(&up)).normalize();
+        let texel = map.texture.color(&texture_coords, footprint);
+        let x = (texel.red * 2.0 - 1.0) as f64 * map.strength;
+        let y = (texel.green * 2.0 - 1.0) as f64 * map.strength;
+        let z = ((texel.blue * 2.0 - 1.0) as f64).max(0.0);
+        let mapped = tangent * x + up * y + normal * z;
+        if mapped.length() > 1e-9 {
+            normal = mapped.normalize();
+        }
+    }
+
+    if let Some(ref map) = material.bump_map {
+        let step = map.texture.texel_size();
+        let height = |du: f32, dv: f32| {
+            let coords = TextureCoords {
+                x: texture_coords.x + du,
+                y: texture_coords.y + dv,
+            };
+            map.texture.color(&coords, footprint).luminance() as f64
+        };
+        let slope_u = (height(step, 0.0) - height(-step, 0.0)) / (2.0 * step as f64);
+        let slope_v = (height(0.0, step) - height(0.0, -step)) / (2.0 * step as f64);
+        let (gradient_u, gradient_v) = gradient.gradients();
+        let slope = (gradient_u * slope_u + gradient_v * slope_v) * map.height;
+        normal = (normal - slope).normalize();
+    }
+    normal
+}
+
+/// Direct lighting for a Blinn-Phong surface: a Lambertian term plus the material's specular
+/// highlights. Refractive surfaces only get the highlights, since the rest of their light
+/// comes from the rays they trace.
+pub fn shade_diffuse(scene: &Scene,
+                 state: &TraceState,
+                 intersection: &Intersection,
                  hit_point: Point,
-                 surface_normal: Vector3)
+                 normals: SurfaceNormals,
+                 view_direction: Vector3,
+                 rng: &mut Rng)
                  -> Color {
-    let texture_coords = element.texture_coords(&hit_point);
+    let surface_normal = normals.shading;
+    let material = intersection.element.material();
+    let has_specular = material.diffuse_weight() < 1.0;
+    let surface_color = match material.surface {
+        SurfaceType::Refractive { .. } if !has_specular => return BLACK,
+        SurfaceType::Refractive { .. } => BLACK,
+        _ => surface_color(scene, state, intersection, hit_point, view_direction),
+    };
+    let specular_color = state.reflectance(material.specular);
+    // Normalization keeps the energy in a Blinn-Phong lobe constant as the exponent changes.
+    let specular_norm = (material.shininess + 8.0) / (8.0 * f32::consts::PI);
     let mut color = BLACK;
     for light in &scene.lights {
-        let direction_to_light = light.direction_from(&hit_point);
-
-        let shadow_ray = Ray {
-            origin: hit_point + (surface_normal * scene.shadow_bias),
-            direction: direction_to_light,
+        let samples = if light.is_point() {
+            1
+        } else {
+            scene.shadow_samples.max(1)
         };
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none() ||
-                       shadow_intersection.unwrap().distance > light.distance(&hit_point);
 
-        let light_intensity = if in_light {
-            light.intensity(&hit_point)
+        let mut light_power = 0.0;
+        let mut specular_power = 0.0;
+        for _ in 0..samples {
+            let sample = light.sample(&hit_point, (rng.next_f64(), rng.next_f64()));
+            // Light from behind the surface can't reach it, however the shading normal leans.
+            if sample.direction.dot(&normals.geometric) <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray {
+                origin: hit_point,
+                direction: sample.direction,
+            }
+            .offset_from(hit_point, normals.geometric, scene.shadow_bias);
+            if !scene.occluded(&shadow_ray, sample.distance) {
+                let power = (surface_normal.dot(&sample.direction) as f32).max(0.0) *
+                            sample.intensity;
+                light_power += power;
+                if has_specular && power > 0.0 {
+                    let half_vector = (sample.direction - view_direction).normalize();
+                    let n_dot_h = (surface_normal.dot(&half_vector) as f32).max(0.0);
+                    specular_power += power * n_dot_h.powf(material.shininess);
+                }
+            }
+        }
+        light_power /= samples as f32;
+        specular_power /= samples as f32;
+        let light_reflected = material.albedo / f32::consts::PI * material.diffuse_weight();
+
+        let emission = state.emission(light);
+        let light_color = emission * light_power * light_reflected;
+        color = color + (surface_color * light_color);
+        if has_specular {
+            color = color + specular_color * emission * (specular_power * specular_norm);
+        }
+    }
+    color
+}
+
+/// Direct lighting for a principled surface: like `shade_diffuse`, but every light sample
+/// is weighted by the full microfacet BRDF.
+pub fn shade_principled(scene: &Scene,
+                        state: &TraceState,
+                        brdf: &Principled,
+                        hit_point: Point,
+                        normals: SurfaceNormals,
+                        view_direction: Vector3,
+                        rng: &mut Rng)
+                        -> Color {
+    let surface_normal = normals.shading;
+    let outgoing = -view_direction;
+    let mut color = BLACK;
+    for light in &scene.lights {
+        let samples = if light.is_point() {
+            1
         } else {
-            0.0
+            scene.shadow_samples.max(1)
         };
-        let material = element.material();
-        let light_power = (surface_normal.dot(&direction_to_light) as f32).max(0.0) *
-                          light_intensity;
-        let light_reflected = material.albedo / f32::consts::PI;
 
-        let light_color = light.color() * light_power * light_reflected;
-        color = color + (material.coloration.color(&texture_coords) * light_color);
+        let mut reflected = BLACK;
+        for _ in 0..samples {
+            let sample = light.sample(&hit_point, (rng.next_f64(), rng.next_f64()));
+            // Light from behind the surface can't reach it, however the shading normal leans.
+            if sample.direction.dot(&normals.geometric) <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray {
+                origin: hit_point,
+                direction: sample.direction,
+            }
+            .offset_from(hit_point, normals.geometric, scene.shadow_bias);
+            let cos = surface_normal.dot(&sample.direction) as f32;
+            if cos > 0.0 && !scene.occluded(&shadow_ray, sample.distance) {
+                let brdf = brdf.evaluate(&surface_normal, &outgoing, &sample.direction);
+                reflected = reflected + brdf * (cos * sample.intensity);
+            }
+        }
+        color = color + state.emission(light) * reflected * (samples as f32).recip();
+    }
+    color
+}
+
+/// A refractive element that a ray is travelling through.
+#[derive(Debug, Clone, Copy)]
+struct Medium<'a> {
+    element: &'a Element,
+    index: f32,
+    absorption: Option<Absorption>,
+    dispersion: Option<Dispersion>,
+}
+
+impl<'a> Medium<'a> {
+    /// The medium inside `element`, if it is refractive.
+    fn inside(element: &'a Element) -> Option<Medium<'a>> {
+        match element.material().surface {
+            SurfaceType::Refractive { index, absorption, dispersion, .. } => {
+                Some(Medium {
+                    element,
+                    index,
+                    absorption,
+                    dispersion,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The refractive index for light of one wavelength, or for white light if
+    /// `wavelength` is None.
+    fn index(&self, wavelength: Option<f32>) -> f32 {
+        match (self.dispersion, wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.index_at(self.index, wavelength),
+            _ => self.index,
+        }
+    }
+}
+
+/// What a ray carries along with it as it is traced.
+///
+/// That starts with the refractive elements it is inside, innermost last. Rays start out in
+/// air, which is left implicit: it has an index of 1 and absorbs nothing. Keeping the whole
+/// stack means a ray leaving a glass inside water carries on in the water, rather than in air.
+///
+/// Once dispersion has split light up, the state also records which color channel the ray
+/// carries, since that decides the indices it sees. In spectral renders it holds the
+/// wavelengths the channels stand for, and converts colors from the scene to match.
+#[derive(Debug, Clone, Default)]
+pub struct TraceState<'a> {
+    media: Vec<Medium<'a>>,
+    channel: Option<usize>,
+    wavelengths: Option<Wavelengths>,
+}
+
+impl<'a> TraceState<'a> {
+    /// The state of a camera ray, carrying light at `wavelengths`, or RGB light if None.
+    pub fn new(wavelengths: Option<Wavelengths>) -> TraceState<'a> {
+        TraceState {
+            media: Vec::new(),
+            channel: None,
+            wavelengths,
+        }
+    }
+
+    /// The refractive index of the innermost medium.
+    pub fn index(&self) -> f32 {
+        self.media.last().map_or(1.0, |medium| medium.index(self.wavelength()))
+    }
+
+    /// The color channel the ray carries, or None for white light.
+    pub fn channel(&self) -> Option<usize> {
+        self.channel
+    }
+
+    /// The wavelength of the channel the ray carries, or None for white light.
+    fn wavelength(&self) -> Option<f32> {
+        self.channel.map(|channel| match self.wavelengths {
+            Some(wavelengths) => wavelengths.0[channel],
+            None => CHANNEL_WAVELENGTHS[channel],
+        })
+    }
+
+    /// The same state, for a ray that carries only `channel`.
+    pub fn dispersed(&self, channel: usize) -> TraceState<'a> {
+        TraceState {
+            channel: Some(channel),
+            ..self.clone()
+        }
+    }
+
+    /// A reflectance or other RGB color from the scene, as the ray's light sees it.
+    pub fn reflectance(&self, color: Color) -> Color {
+        self.wavelengths.map_or(color, |wavelengths| wavelengths.upsample(color))
+    }
+
+    /// The light that `light` emits, in the ray's channels.
+    pub fn emission(&self, light: &Light) -> Color {
+        self.wavelengths.map_or_else(|| light.color(), |wavelengths| light.emission(&wavelengths))
+    }
+
+    /// Converts light the ray carries to linear RGB.
+    pub fn to_rgb(&self, color: Color) -> Color {
+        self.wavelengths.map_or(color, |wavelengths| wavelengths.to_rgb(color))
+    }
+
+    /// Scales light that travelled `distance` through the innermost medium by the share of it
+    /// that the medium lets through.
+    pub fn attenuate(&self, color: Color, distance: f64) -> Color {
+        match self.media.last().and_then(|medium| medium.absorption) {
+            Some(absorption) => {
+                let absorption = Absorption {
+                    color: self.reflectance(absorption.color),
+                    ..absorption
+                };
+                color * absorption.transmittance(distance)
+            }
+            None => color,
+        }
+    }
+
+    /// The state of a ray after passing through the surface of `element`, going into it if
+    /// `entering` and out of it otherwise.
+    pub fn crossing(&self, element: &'a Element, entering: bool) -> TraceState<'a> {
+        let mut media = self.media.clone();
+        if entering {
+            media.extend(Medium::inside(element));
+        } else if let Some(position) = media.iter().rposition(|m| ptr::eq(m.element, element)) {
+            media.remove(position);
+        }
+        TraceState {
+            media,
+            ..self.clone()
+        }
     }
-    color.clamp()
 }
 
-fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
+fn get_color<'a>(scene: &'a Scene,
+                 ray: &Ray,
+                 state: &TraceState<'a>,
+                 intersection: &Intersection<'a>,
+                 depth: u32,
+                 rng: &mut Rng)
+                 -> Color {
     let hit = ray.origin + (ray.direction * intersection.distance);
-    let normal = intersection.element.surface_normal(&hit);
+    let normals = surface_normals(scene, intersection, hit, ray.direction);
+    let (geometric, normal) = (normals.geometric, normals.shading);
+    let bias = scene.shadow_bias;
 
     let material = intersection.element.material();
     match material.surface {
-        SurfaceType::Diffuse => shade_diffuse(scene, intersection.element, hit, normal),
-        SurfaceType::Reflective { reflectivity } => {
-            let mut color = shade_diffuse(scene, intersection.element, hit, normal);
-            let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
+        SurfaceType::Diffuse => {
+            shade_diffuse(scene, state, intersection, hit, normals, ray.direction, rng)
+        }
+        SurfaceType::Reflective { reflectivity, roughness } => {
+            let mut color =
+                shade_diffuse(scene, state, intersection, hit, normals, ray.direction, rng);
+            let reflection_color = trace_glossy(scene, state, roughness, depth, rng, |rng| {
+                let reflection =
+                    Ray::create_glossy_reflection(normal, ray.direction, hit, bias, roughness, rng);
+                Some(reflection.offset_from(hit, geometric, bias))
+            });
             color = color * (1.0 - reflectivity);
-            color = color + (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity);
+            color = color + (reflection_color * reflectivity);
             color
         }
+        SurfaceType::Refractive { dispersion: Some(_), .. } if state.channel().is_none() => {
+            // Each channel bends by its own index, so white light splits into a ray apiece.
+            (0..3).fold(BLACK, |color, channel| {
+                let dispersed = state.dispersed(channel);
+                let traced = get_color(scene, ray, &dispersed, intersection, depth, rng);
+                color + traced.isolate(channel)
+            })
+        }
         SurfaceType::Refractive {
-            index,
             transparency,
+            roughness,
+            ..
         } => {
             let mut refraction_color = BLACK;
+            let (transmitted_state, index) = refraction(state, intersection, ray, geometric);
+            let normal = refracting_normal(normal, geometric, ray.direction);
             let kr = fresnel(ray.direction, normal, index) as f32;
-            let surface_color = material
-                .coloration
-                .color(&intersection.element.texture_coords(&hit));
+            let surface_color = surface_color(scene, state, intersection, hit, ray.direction);
 
             if kr < 1.0 {
-                let transmission_ray =
-                    Ray::create_transmission(normal, ray.direction, hit, scene.shadow_bias, index)
-                        .unwrap();
-                refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
+                refraction_color =
+                    trace_glossy(scene, &transmitted_state, roughness, depth, rng, |rng| {
+                        Ray::create_glossy_transmission(normal,
+                                                        ray.direction,
+                                                        hit,
+                                                        bias,
+                                                        index,
+                                                        roughness,
+                                                        rng)
+                            .map(|transmission| transmission.offset_from(hit, geometric, bias))
+                    });
             }
 
-            let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
+            let reflection_color = trace_glossy(scene, state, roughness, depth, rng, |rng| {
+                let reflection =
+                    Ray::create_glossy_reflection(normal, ray.direction, hit, bias, roughness, rng);
+                Some(reflection.offset_from(hit, geometric, bias))
+            });
             let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
             color = color * transparency * surface_color;
+            color + shade_diffuse(scene, state, intersection, hit, normals, ray.direction, rng)
+        }
+        SurfaceType::Principled { metallic, roughness, specular } => {
+            // Whitted tracing only follows specular paths, so the rest of the specular lobe
+            // is estimated with one sampled reflection; the diffuse lobe gets direct light.
+            let base_color = surface_color(scene, state, intersection, hit, ray.direction) *
+                             material.albedo;
+            let brdf = Principled::new(base_color, metallic, roughness, specular);
+            let mut color =
+                shade_principled(scene, state, &brdf, hit, normals, ray.direction, rng);
+            if let Some(sample) = brdf.sample_specular(&normal, &-ray.direction, rng) {
+                let reflection_ray = Ray {
+                    origin: hit,
+                    direction: sample.direction,
+                }
+                .offset_from(hit, geometric, bias);
+                color = color +
+                        cast_ray(scene, &reflection_ray, state, depth + 1, rng) * sample.weight;
+            }
             color
         }
     }
 }
 
-fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
+/// Averages the light along rays from `next_ray`, which may give none (as when light is
+/// totally internally reflected). A smooth surface needs just one ray, and so do bounces past
+/// the first, so that branching doesn't multiply with depth; otherwise `glossy_samples` rays
+/// are averaged.
+fn trace_glossy<'a, F>(scene: &'a Scene,
+                       state: &TraceState<'a>,
+                       roughness: f32,
+                       depth: u32,
+                       rng: &mut Rng,
+                       mut next_ray: F)
+                       -> Color
+    where F: FnMut(&mut Rng) -> Option<Ray>
+{
+    let samples = if roughness > 0.0 && depth == 0 {
+        scene.glossy_samples.max(1)
+    } else {
+        1
+    };
+    let mut color = BLACK;
+    for _ in 0..samples {
+        if let Some(ray) = next_ray(rng) {
+            color = color + cast_ray(scene, &ray, state, depth + 1, rng);
+        }
+    }
+    color * (samples as f32).recip()
+}
+
+/// Works out where a ray refracting through the surface of a refractive element ends up:
+/// the state of the transmitted ray, with the media it travels through, and the index to
+/// refract with. That is the element's index, for the channel the ray carries, relative to
+/// the medium on its outside, which is what `create_transmission` and `fresnel` expect.
+/// `normal` should be the geometric normal: a bumped shading normal can tilt far enough to
+/// mistake a ray leaving the element for one entering it.
+pub fn refraction<'a>(state: &TraceState<'a>,
+                      intersection: &Intersection<'a>,
+                      ray: &Ray,
+                      normal: Vector3)
+                      -> (TraceState<'a>, f32) {
+    let entering = ray.direction.dot(&normal) < 0.0;
+    let transmitted = state.crossing(intersection.element, entering);
+    let outside_index = if entering {
+        state.index()
+    } else {
+        transmitted.index()
+    };
+    let index = Medium::inside(intersection.element).map_or(1.0, |m| m.index(state.wavelength()));
+    (transmitted, index / outside_index)
+}
+
+/// Returns the normal to refract with where a ray travelling along `incident` meets a
+/// surface: the shading normal, unless it tilts so far that it disagrees with the geometric
+/// normal about which side of the surface the ray arrives from.
+pub fn refracting_normal(shading_normal: Vector3,
+                         geometric_normal: Vector3,
+                         incident: Vector3)
+                         -> Vector3 {
+    if incident.dot(&shading_normal) * incident.dot(&geometric_normal) > 0.0 {
+        shading_normal
+    } else {
+        geometric_normal
+    }
+}
+
+/// Returns the share of light reflected where a ray meets a refractive surface, with
+/// `index` relative to the medium on the side the normal points towards.
+pub fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let i_dot_n = incident.dot(&normal);
     let mut eta_i = 1.0;
     let mut eta_t = index as f64;
@@ -285,20 +1196,91 @@ fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
         return 1.0;
     } else {
         let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
-        let cos_i = cos_t.abs();
+        let cos_i = i_dot_n.abs();
         let r_s = ((eta_t * cos_i) - (eta_i * cos_t)) / ((eta_t * cos_i) + (eta_i * cos_t));
         let r_p = ((eta_i * cos_i) - (eta_t * cos_t)) / ((eta_i * cos_i) + (eta_t * cos_t));
         return (r_s * r_s + r_p * r_p) / 2.0;
     }
 }
 
-pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
+/// Returns the light arriving along `ray`, which carries `state`.
+pub fn cast_ray<'a>(scene: &'a Scene,
+                    ray: &Ray,
+                    state: &TraceState<'a>,
+                    depth: u32,
+                    rng: &mut Rng)
+                    -> Color {
     if depth >= scene.max_recursion_depth {
         return BLACK;
     }
 
     let intersection = scene.trace(&ray);
     intersection
-        .map(|i| get_color(scene, &ray, &i, depth))
+        .map(|i| state.attenuate(get_color(scene, &ray, state, &i, depth, rng), i.distance))
         .unwrap_or(BLACK)
 }
+
+/// Returns the offset of sample `index` of pixel (x, y) from the pixel center, in the unit
+/// square [-0.5, 0.5)^2. A square number of samples is laid out on a grid. Any other number
+/// is split into as many columns and as many rows, and each sample takes one of each
+/// (N-rooks sampling), so that no part of the pixel is left out. Rows are dealt out in a
+/// shuffled order, reshuffled for every pixel when the samples are jittered.
+pub fn sample_offset(scene: &Scene, x: u32, y: u32, index: u32, rng: &mut Rng) -> (f64, f64) {
+    let samples = scene.samples_per_pixel.max(1);
+    let side = (samples as f64).sqrt().round() as u32;
+    let (column, row, columns, rows) = if side * side == samples {
+        (index % side, index / side, side, side)
+    } else {
+        let seed = match scene.sample_pattern {
+            SamplePattern::Stratified => 0,
+            SamplePattern::Jittered => (Rng::for_sample(x, y, samples).next_u64() >> 32) as u32,
+        };
+        (index, permute(index, samples, seed), samples, samples)
+    };
+    let (jitter_x, jitter_y) = match scene.sample_pattern {
+        SamplePattern::Stratified => (0.5, 0.5),
+        SamplePattern::Jittered => (rng.next_f64(), rng.next_f64()),
+    };
+    (((column as f64 + jitter_x) / columns as f64) - 0.5,
+     ((row as f64 + jitter_y) / rows as f64) - 0.5)
+}
+
+/// Traces one sample for pixel (x, y) and returns its color along with its filter weight.
+pub fn render_sample(scene: &Scene, x: u32, y: u32, index: u32) -> (Color, f32) {
+    let mut rng = Rng::for_sample(x, y, index);
+    let (offset_x, offset_y) = sample_offset(scene, x, y, index, &mut rng);
+    let radius = scene.filter.radius();
+    let (dx, dy) = (offset_x * 2.0 * radius, offset_y * 2.0 * radius);
+    let weight = scene.filter.weight(dx, dy) as f32;
+
+    let ray = Ray::create_prime(x as f64 + 0.5 + dx, y as f64 + 0.5 + dy, scene);
+    let wavelengths = if scene.spectral {
+        // Each of the pixel's samples starts in its own slice of the spectrum, so that
+        // together they cover it evenly.
+        let samples = scene.samples_per_pixel.max(1) as f64;
+        Some(Wavelengths::sample((index as f64 + rng.next_f64()) / samples))
+    } else {
+        None
+    };
+    let state = TraceState::new(wavelengths);
+    let color = match scene.integrator {
+        Integrator::Whitted => cast_ray(scene, &ray, &state, 0, &mut rng),
+        Integrator::PathTracing => path_tracing::trace_path(scene, &ray, &state, 0, &mut rng),
+    };
+    (state.to_rgb(color), weight)
+}
+
+pub fn render_pixel(scene: &Scene, x: u32, y: u32) -> Color {
+    let mut color = BLACK;
+    let mut total_weight = 0.0;
+    for index in 0..scene.samples_per_pixel.max(1) {
+        let (sample, weight) = render_sample(scene, x, y, index);
+        color = color + sample * weight;
+        total_weight += weight;
+    }
+    if total_weight > 0.0 {
+        color * total_weight.recip()
+    } else {
+        BLACK
+    }
+}
@@ -0,0 +1,85 @@
+use vector::Vector3;
+use std::f64::consts::PI;
+
+/// A small, fast pseudo-random generator (SplitMix64).
+///
+/// Renders seed a fresh generator from each pixel and sample index, so the random
+/// decisions made for a sample never depend on what other threads are doing.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    Rng { state: seed }
+  }
+
+  pub fn for_sample(x: u32, y: u32, sample: u32) -> Rng {
+    let seed = ((x as u64) << 40) ^ ((y as u64) << 20) ^ (sample as u64);
+    let mut rng = Rng::new(seed);
+    // Decorrelate neighbouring seeds before handing the generator out.
+    rng.next_u64();
+    rng
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+  }
+
+  /// Returns a uniformly distributed value in [0, 1).
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+  }
+}
+
+/// Returns where `index` lands in a shuffle of `0..count`, a different shuffle for every
+/// `seed`. Each index gets its own position without building the whole permutation (Kensler,
+/// "Correlated Multi-Jittered Sampling", 2013).
+pub fn permute(index: u32, count: u32, seed: u32) -> u32 {
+  let mut mask = count.saturating_sub(1);
+  mask |= mask >> 1;
+  mask |= mask >> 2;
+  mask |= mask >> 4;
+  mask |= mask >> 8;
+  mask |= mask >> 16;
+  let mut i = index;
+  // Scramble within the next power of two, and try again until the result is in range.
+  loop {
+    i ^= seed;
+    i = i.wrapping_mul(0xe170_893d);
+    i ^= seed >> 16;
+    i ^= (i & mask) >> 4;
+    i ^= seed >> 8;
+    i = i.wrapping_mul(0x0929_eb3f);
+    i ^= seed >> 23;
+    i ^= (i & mask) >> 1;
+    i = i.wrapping_mul(1 | seed >> 27);
+    i = i.wrapping_mul(0x6935_fa69);
+    i ^= (i & mask) >> 11;
+    i = i.wrapping_mul(0x74dc_b303);
+    i ^= (i & mask) >> 2;
+    i = i.wrapping_mul(0x9e50_1cc3);
+    i ^= (i & mask) >> 2;
+    i = i.wrapping_mul(0xc860_a3df);
+    i &= mask;
+    i ^= i >> 5;
+    if i < count {
+      return ((i as u64 + seed as u64) % count as u64) as u32;
+    }
+  }
+}
+
+/// Returns a direction in the hemisphere around `normal`, with probability proportional to
+/// the cosine of its angle to the normal.
+pub fn cosine_hemisphere(normal: &Vector3, rng: &mut Rng) -> Vector3 {
+  let (u1, u2) = (rng.next_f64(), rng.next_f64());
+  let radius = u1.sqrt();
+  let (sin, cos) = (2.0 * PI * u2).sin_cos();
+  let (tangent, bitangent) = normal.orthonormal_basis();
+  (tangent * (radius * cos) + bitangent * (radius * sin) + *normal * (1.0 - u1).max(0.0).sqrt())
+    .normalize()
+}
@@ -1,29 +1,54 @@
 use point::Point;
 use vector::Vector3;
-use rendering::{Intersectable, Ray, TextureCoords};
-use std::ops::{Mul, Add};
+use rendering::{Intersectable, Hit, Ray, TextureCoords};
+use bvh::{BoundingBox, Bvh};
+use transform::Transform;
+use spectrum::{Spectrum, Wavelengths};
+use obj;
+use noise;
+use std::ops::{Mul, Add, Deref};
 use image::{Rgba, Pixel, DynamicImage, GenericImage};
 use serde::{Deserialize, Deserializer};
+use serde::de;
 use image;
+use std::cell::RefCell;
 use std::fmt;
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde_json;
+use serde_yaml;
+use error::Error;
+use std::thread;
+use std::sync::OnceLock;
 
 
 // 
 // COLOR
 //
 
-const GAMMA: f32 = 2.2;
-
-fn gamma_encode(linear: f32) -> f32 {
-  linear.powf(1.0 / GAMMA)
+/// The sRGB transfer function, from linear light to encoded values.
+fn srgb_encode(linear: f32) -> f32 {
+  if linear <= 0.003_130_8 {
+    linear * 12.92
+  } else {
+    1.055 * linear.powf(1.0 / 2.4) - 0.055
+  }
 }
 
-fn gamma_decode(encoded: f32) -> f32 {
-  encoded.powf(GAMMA)
+/// The inverse sRGB transfer function, from encoded values to linear light.
+fn srgb_decode(encoded: f32) -> f32 {
+  if encoded <= 0.040_45 {
+    encoded / 12.92
+  } else {
+    ((encoded + 0.055) / 1.055).powf(2.4)
+  }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+/// A 4x4 Bayer matrix, used to spread quantization error in a fixed, tiled pattern.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
 pub struct Color {
   pub red: f32,
   pub green: f32,
@@ -38,18 +63,56 @@ impl Color {
     }
   }
 
+  /// Converts linear radiance to an 8-bit sRGB color, rounding to the nearest level. Anything
+  /// outside [0, 1] is clipped here, so keep working with unclamped colors until the very end.
   pub fn to_rgba(&self) -> Rgba<u8> {
-    Rgba::from_channels((gamma_encode(self.red) * 255.0) as u8,
-                        (gamma_encode(self.green) * 255.0) as u8,
-                        (gamma_encode(self.blue) * 255.0) as u8,
-                        255)
+    self.quantize(0.0)
+  }
+
+  /// Like `to_rgba`, but offsets each pixel by an ordered dither threshold before rounding,
+  /// which breaks up banding in smooth gradients.
+  pub fn to_rgba_dithered(&self, x: u32, y: u32) -> Rgba<u8> {
+    let level = BAYER[y as usize % 4][x as usize % 4] as f32;
+    self.quantize((level + 0.5) / 16.0 - 0.5)
   }
 
+  fn quantize(&self, offset: f32) -> Rgba<u8> {
+    let color = self.clamp();
+    let level = |c: f32| (srgb_encode(c) * 255.0 + offset).round().clamp(0.0, 255.0) as u8;
+    Rgba::from_channels(level(color.red), level(color.green), level(color.blue), 255)
+  }
+
+  /// Decodes an 8-bit sRGB color to linear values.
   pub fn from_rgba(rgba: Rgba<u8>) -> Color {
     Color {
-      red: gamma_decode((rgba.data[0] as f32) / 255.0),
-      green: gamma_decode((rgba.data[1] as f32) / 255.0),
-      blue: gamma_decode((rgba.data[2] as f32) / 255.0),
+      red: srgb_decode((rgba.data[0] as f32) / 255.0),
+      green: srgb_decode((rgba.data[1] as f32) / 255.0),
+      blue: srgb_decode((rgba.data[2] as f32) / 255.0),
+    }
+  }
+
+  /// Keeps one channel (0 for red, 1 for green, 2 for blue) and zeroes the others.
+  pub fn isolate(&self, channel: usize) -> Color {
+    let mut isolated = Color::default();
+    match channel {
+      0 => isolated.red = self.red,
+      1 => isolated.green = self.green,
+      _ => isolated.blue = self.blue,
+    }
+    isolated
+  }
+
+  /// The brightness of a linear color as the eye perceives it (Rec. 709 weights).
+  pub fn luminance(&self) -> f32 {
+    0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+  }
+
+  /// Reads an 8-bit color whose values are already linear, such as a normal or roughness map.
+  pub fn from_rgba_linear(rgba: Rgba<u8>) -> Color {
+    Color {
+      red: (rgba.data[0] as f32) / 255.0,
+      green: (rgba.data[1] as f32) / 255.0,
+      blue: (rgba.data[2] as f32) / 255.0,
     }
   }
 }
@@ -99,41 +162,95 @@ impl Add for Color {
 // LIGHT
 //
 
+/// Every light has a `color` and an `intensity`. It may also have an emission `spectrum`,
+/// tinted by its color, which spectral renders sample and RGB renders reduce to a color.
 #[derive(Deserialize, Debug)]
 pub enum Light {
   Directional(DirectionalLight),
   Spherical(SphericalLight),
+  Rectangular(RectangularLight),
+  Disk(DiskLight),
 }
+
+/// A point on a light as seen from a surface: the direction and distance to it, and the
+/// irradiance it delivers to a surface facing it head-on.
+#[derive(Debug)]
+pub struct LightSample {
+  pub direction: Vector3,
+  pub distance: f64,
+  pub intensity: f32,
+}
+
 impl Light {
+  /// The light's color in RGB, tinted by its emission spectrum if it has one.
   pub fn color(&self) -> Color {
+    let (color, spectrum) = self.emitter();
+    spectrum.map_or(color, |spectrum| color * spectrum.rgb())
+  }
+
+  /// The light's emission at each of `wavelengths`: its color upsampled to a spectrum,
+  /// times its emission spectrum, or white light if it has none.
+  pub fn emission(&self, wavelengths: &Wavelengths) -> Color {
+    let (color, spectrum) = self.emitter();
+    let emitted = spectrum.map_or_else(|| wavelengths.white(), |s| wavelengths.evaluate(s));
+    wavelengths.upsample(color) * emitted
+  }
+
+  fn emitter(&self) -> (Color, Option<&Spectrum>) {
     match *self {
-      Light::Directional(ref d) => d.color,
-      Light::Spherical(ref s) => s.color,
+      Light::Directional(ref d) => (d.color, d.spectrum.as_ref()),
+      Light::Spherical(ref s) => (s.color, s.spectrum.as_ref()),
+      Light::Rectangular(ref r) => (r.color, r.spectrum.as_ref()),
+      Light::Disk(ref d) => (d.color, d.spectrum.as_ref()),
     }
   }
 
-  pub fn direction_from(&self, hit_point: &Point) -> Vector3 {
+  /// Returns true if the light has no extent, so a single sample sees all of it.
+  pub fn is_point(&self) -> bool {
     match *self {
-      Light::Directional(ref d) => -d.direction,
-      Light::Spherical(ref s) => (s.position - *hit_point).normalize(),
+      Light::Directional(_) => true,
+      Light::Spherical(ref s) => s.radius <= 0.0,
+      Light::Rectangular(_) | Light::Disk(_) => false,
     }
   }
 
-  pub fn intensity(&self, hit_point: &Point) -> f32 {
+  /// Picks a point on the light using the two uniform random numbers in `u`.
+  pub fn sample(&self, hit_point: &Point, u: (f64, f64)) -> LightSample {
     match *self {
-      Light::Directional(ref d) => d.intensity,
-      Light::Spherical(ref s) => {
-        let r2 = (s.position - *hit_point).norm() as f32;
-        s.intensity / (4.0 * ::std::f32::consts::PI * r2)
+      Light::Directional(ref d) => {
+        LightSample {
+          direction: -d.direction,
+          distance: ::std::f64::INFINITY,
+          intensity: d.intensity,
+        }
+      }
+      Light::Spherical(ref s) => s.sample(hit_point, u),
+      Light::Rectangular(ref r) => {
+        let point = r.position + r.u * (u.0 - 0.5) + r.v * (u.1 - 0.5);
+        let normal = r.u.cross(&r.v).normalize();
+        sample_area(hit_point, &point, &normal, r.intensity)
+      }
+      Light::Disk(ref d) => {
+        let (tangent, bitangent) = d.normal.orthonormal_basis();
+        let radius = d.radius * u.0.sqrt();
+        let (sin, cos) = (2.0 * ::std::f64::consts::PI * u.1).sin_cos();
+        let point = d.position + tangent * (radius * cos) + bitangent * (radius * sin);
+        sample_area(hit_point, &point, &d.normal, d.intensity)
       }
     }
   }
+}
 
-  pub fn distance(&self, hit_point: &Point) -> f64 {
-    match *self {
-      Light::Directional(_) => ::std::f64::INFINITY,
-      Light::Spherical(ref s) => (s.position - *hit_point).length(),
-    }
+/// Samples a point on a one-sided Lambertian emitter whose total power is `power`.
+fn sample_area(hit_point: &Point, point: &Point, normal: &Vector3, power: f32) -> LightSample {
+  let to_light = *point - *hit_point;
+  let distance = to_light.length();
+  let direction = to_light * distance.recip();
+  let cos_light = (-direction.dot(normal)).max(0.0);
+  LightSample {
+    direction,
+    distance,
+    intensity: power * cos_light as f32 / (::std::f32::consts::PI * (distance * distance) as f32),
   }
 }
 
@@ -143,6 +260,8 @@ pub struct DirectionalLight {
   pub direction: Vector3,
   pub color: Color,
   pub intensity: f32,
+  #[serde(default)]
+  pub spectrum: Option<Spectrum>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -150,6 +269,74 @@ pub struct SphericalLight {
   pub position: Point,
   pub color: Color,
   pub intensity: f32,
+  /// A radius of zero makes this a point light with hard shadows.
+  #[serde(default)]
+  pub radius: f64,
+  #[serde(default)]
+  pub spectrum: Option<Spectrum>,
+}
+impl SphericalLight {
+  fn sample(&self, hit_point: &Point, u: (f64, f64)) -> LightSample {
+    let to_center = self.position - *hit_point;
+    let distance2 = to_center.norm();
+    let point_light = LightSample {
+      direction: to_center.normalize(),
+      distance: distance2.sqrt(),
+      intensity: self.intensity / (4.0 * ::std::f32::consts::PI * distance2 as f32),
+    };
+    let radius2 = self.radius * self.radius;
+    if radius2 <= 0.0 || distance2 <= radius2 {
+      return point_light;
+    }
+
+    // Sample the cone of directions the sphere covers. Every direction in it sees the same
+    // radiance, so each sample delivers radiance times the cone's solid angle.
+    let cos_max = (1.0 - radius2 / distance2).sqrt();
+    let cos_theta = 1.0 - u.0 * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (2.0 * ::std::f64::consts::PI * u.1).sin_cos();
+    let axis = point_light.direction;
+    let (tangent, bitangent) = axis.orthonormal_basis();
+    let direction = axis * cos_theta + tangent * (sin_theta * cos_phi) +
+                    bitangent * (sin_theta * sin_phi);
+
+    let adj = to_center.dot(&direction);
+    let distance = adj - (radius2 - (distance2 - adj * adj)).max(0.0).sqrt();
+    let pi = ::std::f32::consts::PI;
+    let radiance = self.intensity / (4.0 * pi * pi * radius2 as f32);
+    let solid_angle = 2.0 * pi * (1.0 - cos_max as f32);
+    LightSample {
+      direction,
+      distance,
+      intensity: radiance * solid_angle,
+    }
+  }
+}
+
+/// A rectangle centered on `position` with edges `u` and `v`, emitting on the side that
+/// `u` x `v` points to.
+#[derive(Deserialize, Debug)]
+pub struct RectangularLight {
+  pub position: Point,
+  pub u: Vector3,
+  pub v: Vector3,
+  pub color: Color,
+  pub intensity: f32,
+  #[serde(default)]
+  pub spectrum: Option<Spectrum>,
+}
+
+/// A disk centered on `position`, emitting on the side its normal points to.
+#[derive(Deserialize, Debug)]
+pub struct DiskLight {
+  pub position: Point,
+  #[serde(deserialize_with="Vector3::deserialize_normalized")]
+  pub normal: Vector3,
+  pub radius: f64,
+  pub color: Color,
+  pub intensity: f32,
+  #[serde(default)]
+  pub spectrum: Option<Spectrum>,
 }
 
 
@@ -160,8 +347,116 @@ pub struct SphericalLight {
 #[derive(Deserialize, Debug)]
 pub enum SurfaceType {
   Diffuse,
-  Reflective { reflectivity: f32 },
-  Refractive { index: f32, transparency: f32 },
+  /// `roughness` blurs the reflection by spreading reflected rays over a GGX lobe, from a
+  /// perfect mirror at 0 to a hazy sheen at 1.
+  Reflective {
+    reflectivity: f32,
+    #[serde(default)]
+    roughness: f32,
+  },
+  /// `index` is the material's refractive index; where it meets another refractive
+  /// material rather than air, the ratio of the two indices is used. `roughness` frosts the
+  /// surface, spreading both reflected and transmitted rays, `absorption` tints light by
+  /// how far it travels inside, and `dispersion` splits it into a spectrum.
+  Refractive {
+    index: f32,
+    transparency: f32,
+    #[serde(default)]
+    roughness: f32,
+    #[serde(default)]
+    absorption: Option<Absorption>,
+    #[serde(default)]
+    dispersion: Option<Dispersion>,
+  },
+  /// A metallic-roughness surface with a GGX microfacet specular lobe. The base color is the
+  /// coloration scaled by albedo. `metallic` blends from a dielectric, whose specular
+  /// reflectance at normal incidence is 8% of `specular`, to a metal tinted by the base
+  /// color; `roughness` runs from a mirror at 0 to a broad, matte sheen at 1. The material's
+  /// Blinn-Phong `specular` and `shininess` are ignored.
+  Principled {
+    #[serde(default)]
+    metallic: f32,
+    #[serde(default="default_roughness")]
+    roughness: f32,
+    #[serde(default="default_principled_specular")]
+    specular: f32,
+  },
+}
+
+/// Light absorbed inside a refractive material, following the Beer-Lambert law: after
+/// travelling a distance d, light is multiplied by `color` raised to the power of
+/// `density * d`. So `color` is the tint of one unit of material at density 1.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Absorption {
+  pub color: Color,
+  #[serde(default="default_absorption_density")]
+  pub density: f32,
+}
+impl Absorption {
+  /// The fraction of each channel left after travelling `distance` through the material.
+  pub fn transmittance(&self, distance: f64) -> Color {
+    let depth = self.density * distance as f32;
+    Color {
+      red: self.color.red.clamp(0.0, 1.0).powf(depth),
+      green: self.color.green.clamp(0.0, 1.0).powf(depth),
+      blue: self.color.blue.clamp(0.0, 1.0).powf(depth),
+    }
+  }
+}
+
+fn default_absorption_density() -> f32 {
+  1.0
+}
+
+/// The wavelengths, in nanometres, that stand for the red, green and blue channels when
+/// light is split by dispersion: roughly where each sRGB primary is strongest.
+pub const CHANNEL_WAVELENGTHS: [f32; 3] = [630.0, 532.0, 465.0];
+
+/// The sodium d line, at which refractive indices are conventionally quoted.
+const D_LINE: f32 = 587.6;
+
+/// How a refractive material's index varies with wavelength, which splits white light into
+/// a spectrum. Coefficients are for wavelengths in micrometres, as in glass catalogs.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum Dispersion {
+  /// Cauchy's equation, n = A + B / w^2 + C / w^4, with A chosen so that the index at the
+  /// sodium d line is the material's `index`. Crown glass has a B of about 0.004.
+  Cauchy {
+    b: f32,
+    #[serde(default)]
+    c: f32,
+  },
+  /// The Sellmeier equation, n^2 = 1 + sum(B w^2 / (w^2 - C)), which gives the index
+  /// outright, in place of the material's `index`.
+  Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+impl Dispersion {
+  /// The refractive index at `wavelength` nanometres, for a material whose index at the d
+  /// line is `index`.
+  pub fn index_at(&self, index: f32, wavelength: f32) -> f32 {
+    match *self {
+      Dispersion::Cauchy { b, c } => {
+        let cauchy = |nm: f32| {
+          let w2 = (nm / 1000.0).powi(2);
+          b / w2 + c / (w2 * w2)
+        };
+        index + cauchy(wavelength) - cauchy(D_LINE)
+      }
+      Dispersion::Sellmeier { b, c } => {
+        let w2 = (wavelength / 1000.0).powi(2);
+        let sum: f32 = b.iter().zip(c.iter()).map(|(b, c)| b * w2 / (w2 - c)).sum();
+        (1.0 + sum).max(1.0).sqrt()
+      }
+    }
+  }
+}
+
+fn default_roughness() -> f32 {
+  0.5
+}
+
+fn default_principled_specular() -> f32 {
+  0.5
 }
 
 #[derive(Deserialize, Debug)]
@@ -169,54 +464,600 @@ pub struct Material {
   pub coloration: Coloration,
   pub albedo: f32,
   pub surface: SurfaceType,
+  /// Color of Blinn-Phong specular highlights, which refractive surfaces show on top of the
+  /// light they reflect and let through. Black, the default, turns them off.
+  #[serde(default)]
+  pub specular: Color,
+  /// Blinn-Phong exponent; higher values give smaller, sharper highlights.
+  #[serde(default="default_shininess")]
+  pub shininess: f32,
+  #[serde(default)]
+  pub normal_map: Option<NormalMap>,
+  #[serde(default)]
+  pub bump_map: Option<BumpMap>,
+}
+impl Material {
+  /// The share of incoming light left for the diffuse term once the specular term has taken
+  /// its part, so that the two together never reflect more light than they receive.
+  pub fn diffuse_weight(&self) -> f32 {
+    1.0 - self.specular.red.max(self.specular.green).max(self.specular.blue).clamp(0.0, 1.0)
+  }
+}
+
+fn default_shininess() -> f32 {
+  32.0
+}
+
+/// A tangent-space normal map. Each texel holds a normal with red along increasing
+/// horizontal texture coordinates, green towards the top of the image and blue out of the
+/// surface, mapped from [-1, 1] to [0, 1]. This is the OpenGL convention.
+#[derive(Deserialize, Debug)]
+pub struct NormalMap {
+  #[serde(deserialize_with="load_data_texture")]
+  pub texture: Texture,
+  /// Scales how far the map tilts the normal; 0 leaves the surface flat.
+  #[serde(default="default_normal_strength")]
+  pub strength: f64,
 }
 
-pub fn load_texture<D>(deserializer: D) -> Result<DynamicImage, D::Error>
+fn default_normal_strength() -> f64 {
+  1.0
+}
+
+/// A height map whose slopes tilt the normal, as if the surface were raised by the
+/// brightness of each texel.
+#[derive(Deserialize, Debug)]
+pub struct BumpMap {
+  #[serde(deserialize_with="load_data_texture")]
+  pub texture: Texture,
+  /// How far a white texel raises the surface, in scene units.
+  pub height: f64,
+}
+
+/// How a texture is sampled between and across its texels.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TextureFilter {
+  /// The single closest texel. Blocky up close and prone to aliasing at a distance.
+  #[default]
+  Nearest,
+  /// A blend of the four closest texels.
+  Bilinear,
+  /// Bilinear lookups in the two mip levels closest to the pixel's footprint, blended.
+  Trilinear,
+}
+
+/// What a texture shows outside the [0, 1] range of texture coordinates.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TextureWrap {
+  /// Tiles the image.
+  #[default]
+  Repeat,
+  /// Tiles the image, flipping every other copy so that edges meet seamlessly.
+  MirrorRepeat,
+  /// Extends the edge texels outwards.
+  ClampToEdge,
+}
+impl TextureWrap {
+  /// Maps a texel index, possibly out of range, into [0, size).
+  fn apply(&self, index: i64, size: u32) -> usize {
+    let size = size as i64;
+    let index = match *self {
+      TextureWrap::Repeat => index.rem_euclid(size),
+      TextureWrap::MirrorRepeat => {
+        let mirrored = index.rem_euclid(2 * size);
+        if mirrored < size {
+          mirrored
+        } else {
+          2 * size - 1 - mirrored
+        }
+      }
+      TextureWrap::ClampToEdge => index.clamp(0, size - 1),
+    };
+    index as usize
+  }
+}
+
+/// Maps a surface's texture coordinates onto a texture: they are scaled, then rotated
+/// counterclockwise by `rotation` degrees, then offset.
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+  pub scale: TextureCoords,
+  pub offset: TextureCoords,
+  pub rotation: f32,
+}
+impl Default for UvTransform {
+  fn default() -> UvTransform {
+    UvTransform {
+      scale: default_uv_scale(),
+      offset: TextureCoords::default(),
+      rotation: 0.0,
+    }
+  }
+}
+impl UvTransform {
+  pub fn apply(&self, texture_coords: &TextureCoords) -> TextureCoords {
+    let x = texture_coords.x * self.scale.x;
+    let y = texture_coords.y * self.scale.y;
+    let (sin, cos) = self.rotation.to_radians().sin_cos();
+    TextureCoords {
+      x: x * cos - y * sin + self.offset.x,
+      y: x * sin + y * cos + self.offset.y,
+    }
+  }
+
+  /// How much the transform stretches distances, at most.
+  fn stretch(&self) -> f32 {
+    self.scale.x.abs().max(self.scale.y.abs())
+  }
+}
+
+fn default_uv_scale() -> TextureCoords {
+  TextureCoords { x: 1.0, y: 1.0 }
+}
+
+/// One level of a texture's mip chain, decoded to linear colors.
+struct MipLevel {
+  width: u32,
+  height: u32,
+  texels: Vec<Color>,
+}
+impl MipLevel {
+  /// Returns the texel at (x, y), using `wrap` for coordinates outside the image.
+  fn texel(&self, x: i64, y: i64, wrap: TextureWrap) -> Color {
+    let x = wrap.apply(x, self.width);
+    let y = wrap.apply(y, self.height);
+    self.texels[y * self.width as usize + x]
+  }
+
+  fn nearest(&self, texture_coords: &TextureCoords, wrap: TextureWrap) -> Color {
+    self.texel((texture_coords.x * self.width as f32).floor() as i64,
+               (texture_coords.y * self.height as f32).floor() as i64,
+               wrap)
+  }
+
+  fn bilinear(&self, texture_coords: &TextureCoords, wrap: TextureWrap) -> Color {
+    // Texel centers sit half a texel in from their edges.
+    let x = texture_coords.x * self.width as f32 - 0.5;
+    let y = texture_coords.y * self.height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = self.texel(x0, y0, wrap) * (1.0 - fx) + self.texel(x0 + 1, y0, wrap) * fx;
+    let bottom = self.texel(x0, y0 + 1, wrap) * (1.0 - fx) +
+                 self.texel(x0 + 1, y0 + 1, wrap) * fx;
+    top * (1.0 - fy) + bottom * fy
+  }
+
+  /// Halves the level in each dimension, rounding down, by averaging the texels under each
+  /// new texel. With an odd size, new texels straddle old ones, which count in proportion to
+  /// how much of them is covered, so the last row and column still contribute.
+  fn downsample(&self) -> MipLevel {
+    let width = (self.width / 2).max(1);
+    let height = (self.height / 2).max(1);
+    let mut texels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+      let rows = covered_texels(y, self.height, height);
+      for x in 0..width {
+        let columns = covered_texels(x, self.width, width);
+        let mut sum = Color::default();
+        for &(y, row_weight) in &rows {
+          for &(x, column_weight) in &columns {
+            sum = sum + self.texel(x, y, TextureWrap::ClampToEdge) * (row_weight * column_weight);
+          }
+        }
+        texels.push(sum);
+      }
+    }
+    MipLevel { width, height, texels }
+  }
+}
+
+/// The texels, out of `from` in a row, that texel `index` covers once they are shrunk to
+/// `to`, each with the share of the new texel that it makes up.
+fn covered_texels(index: u32, from: u32, to: u32) -> Vec<(i64, f32)> {
+  let scale = from as f32 / to as f32;
+  let (start, end) = (index as f32 * scale, (index + 1) as f32 * scale);
+  (start.floor() as i64..end.ceil() as i64)
+    .map(|i| (i, (end.min(i as f32 + 1.0) - start.max(i as f32)) / scale))
+    .collect()
+}
+
+/// An image mapped onto a surface through its texture coordinates. Texels are decoded to
+/// linear colors once, when the texture is loaded.
+pub struct Texture {
+  levels: Vec<MipLevel>,
+  pub filter: TextureFilter,
+  pub wrap: TextureWrap,
+  pub transform: UvTransform,
+}
+impl Texture {
+  /// Decodes `image`, treating its values as linear rather than sRGB if `linear` is set, as
+  /// it must be for data such as normal or roughness maps. Mip levels are only built for
+  /// trilinear filtering. The texture repeats, untransformed, until told otherwise.
+  pub fn new(image: &DynamicImage, linear: bool, filter: TextureFilter) -> Texture {
+    let (width, height) = image.dimensions();
+    let texels = image.pixels()
+      .map(|(_, _, rgba)| if linear {
+        Color::from_rgba_linear(rgba)
+      } else {
+        Color::from_rgba(rgba)
+      })
+      .collect();
+    let mut levels = vec![MipLevel { width, height, texels }];
+    if filter == TextureFilter::Trilinear {
+      while levels.last().is_some_and(|l| l.width > 1 || l.height > 1) {
+        let next = levels[levels.len() - 1].downsample();
+        levels.push(next);
+      }
+    }
+    Texture {
+      levels,
+      filter,
+      wrap: TextureWrap::default(),
+      transform: UvTransform::default(),
+    }
+  }
+
+  /// The width of one texel, in the surface's texture coordinates.
+  pub fn texel_size(&self) -> f32 {
+    let base = &self.levels[0];
+    (base.width.max(base.height) as f32 * self.transform.stretch()).recip()
+  }
+
+  /// Looks up the texture at the surface's `texture_coords`. `footprint` is the width, in
+  /// the same coordinates, of the area the pixel covers, which selects the mip level.
+  pub fn color(&self, texture_coords: &TextureCoords, footprint: f32) -> Color {
+    let texture_coords = &self.transform.apply(texture_coords);
+    let wrap = self.wrap;
+    let base = &self.levels[0];
+    match self.filter {
+      TextureFilter::Nearest => base.nearest(texture_coords, wrap),
+      TextureFilter::Bilinear => base.bilinear(texture_coords, wrap),
+      TextureFilter::Trilinear => {
+        let footprint = footprint * self.transform.stretch();
+        let texels = footprint * base.width.max(base.height) as f32;
+        let lod = texels.log2().max(0.0).min((self.levels.len() - 1) as f32);
+        let level = lod.floor() as usize;
+        let blend = lod - level as f32;
+        let fine = self.levels[level].bilinear(texture_coords, wrap);
+        if blend <= 0.0 {
+          return fine;
+        }
+        fine * (1.0 - blend) + self.levels[level + 1].bilinear(texture_coords, wrap) * blend
+      }
+    }
+  }
+}
+
+impl fmt::Debug for Texture {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f,
+           "Texture({}x{}, {:?}, {:?})",
+           self.levels[0].width,
+           self.levels[0].height,
+           self.filter,
+           self.wrap)
+  }
+}
+
+thread_local! {
+  /// The directory of the scene file that `Scene::load` is reading on this thread, if any.
+  static SCENE_DIRECTORY: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Makes the scene file at `path` the one that asset paths are relative to on this thread,
+/// until it is dropped. Dropping it clears the directory even if parsing panics.
+struct SceneDirectory;
+impl SceneDirectory {
+  fn enter(path: &Path) -> SceneDirectory {
+    let directory = path.parent().map(Path::to_path_buf);
+    SCENE_DIRECTORY.with(|d| *d.borrow_mut() = directory);
+    SceneDirectory
+  }
+}
+
+impl Drop for SceneDirectory {
+  fn drop(&mut self) {
+    SCENE_DIRECTORY.with(|d| *d.borrow_mut() = None);
+  }
+}
+
+/// Resolves a path from a scene file against the file's directory.
+fn scene_relative(path: &Path) -> PathBuf {
+  SCENE_DIRECTORY.with(|directory| match *directory.borrow() {
+    Some(ref directory) => directory.join(path),
+    None => path.to_path_buf(),
+  })
+}
+
+/// A texture is given either as a bare path, relative to the scene file, or as a map with
+/// its options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureSource {
+  Path(PathBuf),
+  Options {
+    path: PathBuf,
+    #[serde(default)]
+    linear: Option<bool>,
+    #[serde(default)]
+    filter: TextureFilter,
+    #[serde(default)]
+    wrap: TextureWrap,
+    #[serde(default="default_uv_scale")]
+    scale: TextureCoords,
+    #[serde(default)]
+    offset: TextureCoords,
+    /// Counterclockwise, in degrees.
+    #[serde(default)]
+    rotation: f32,
+  },
+}
+
+pub fn load_texture<D>(deserializer: D) -> Result<Texture, D::Error>
+  where D: Deserializer
+{
+  load_texture_or_data(deserializer, false)
+}
+
+/// Loads a texture that holds data rather than colors, such as a normal or bump map, so it
+/// is treated as linear unless it says otherwise.
+pub fn load_data_texture<D>(deserializer: D) -> Result<Texture, D::Error>
   where D: Deserializer
 {
-  let path = PathBuf::deserialize(deserializer)?;
-  Ok(image::open(path).expect("Unable to open texture file"))
+  load_texture_or_data(deserializer, true)
+}
+
+fn load_texture_or_data<D>(deserializer: D, linear_by_default: bool) -> Result<Texture, D::Error>
+  where D: Deserializer
+{
+  let open = |path: &Path| {
+    let path = scene_relative(path);
+    image::open(&path).map_err(|e| {
+      de::Error::custom(format!("unable to open texture {}: {}", path.display(), e))
+    })
+  };
+  match TextureSource::deserialize(deserializer)? {
+    TextureSource::Path(path) => {
+      Ok(Texture::new(&open(&path)?, linear_by_default, TextureFilter::default()))
+    }
+    TextureSource::Options { path, linear, filter, wrap, scale, offset, rotation } => {
+      let linear = linear.unwrap_or(linear_by_default);
+      let mut texture = Texture::new(&open(&path)?, linear, filter);
+      texture.wrap = wrap;
+      texture.transform = UvTransform { scale, offset, rotation };
+      Ok(texture)
+    }
+  }
+}
+
+/// Where a procedural pattern is evaluated.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum PatternSpace {
+  /// The surface's texture coordinates, as the point (x, y, 0). The pattern follows the
+  /// surface like an image texture would.
+  #[default]
+  Uv,
+  /// The hit position in the scene, so that objects look carved out of a solid block.
+  Position,
+}
+
+fn default_pattern_scale() -> f64 {
+  1.0
+}
+
+fn default_octaves() -> u32 {
+  4
+}
+
+fn default_persistence() -> f64 {
+  0.5
+}
+
+fn default_turbulence() -> f64 {
+  5.0
+}
+
+fn default_rings() -> f64 {
+  8.0
+}
+
+/// Alternating cubes of two colors, one unit (before scaling) across.
+#[derive(Deserialize, Debug)]
+pub struct Checker {
+  pub even: Color,
+  pub odd: Color,
+  #[serde(default="default_pattern_scale")]
+  pub scale: f64,
+  #[serde(default)]
+  pub space: PatternSpace,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum GradientShape {
+  /// Bands perpendicular to the line from `start` to `end`.
+  #[default]
+  Linear,
+  /// Rings centered on `start`, reaching `to` at the distance to `end`.
+  Radial,
+}
+
+/// A blend from `from` at `start` to `to` at `end`, held at the end colors beyond them.
+#[derive(Deserialize, Debug)]
+pub struct Gradient {
+  pub from: Color,
+  pub to: Color,
+  pub start: Point,
+  pub end: Point,
+  #[serde(default)]
+  pub shape: GradientShape,
+  #[serde(default)]
+  pub space: PatternSpace,
+}
+
+/// A cloudy blend of two colors driven by fractal Perlin noise. With one octave this is
+/// plain Perlin noise.
+#[derive(Deserialize, Debug)]
+pub struct Noise {
+  pub from: Color,
+  pub to: Color,
+  #[serde(default="default_pattern_scale")]
+  pub scale: f64,
+  #[serde(default="default_octaves")]
+  pub octaves: u32,
+  /// How much each octave's amplitude shrinks relative to the last.
+  #[serde(default="default_persistence")]
+  pub persistence: f64,
+  #[serde(default)]
+  pub space: PatternSpace,
+}
+
+/// Veins of `to` through `from`: bands across the x axis, bent by turbulence.
+#[derive(Deserialize, Debug)]
+pub struct Marble {
+  pub from: Color,
+  pub to: Color,
+  #[serde(default="default_pattern_scale")]
+  pub scale: f64,
+  #[serde(default="default_octaves")]
+  pub octaves: u32,
+  /// How far the noise bends the veins.
+  #[serde(default="default_turbulence")]
+  pub turbulence: f64,
+  #[serde(default)]
+  pub space: PatternSpace,
+}
+
+/// Growth rings around the z axis, shading from `from` to `to` across each ring, wobbled by
+/// noise.
+#[derive(Deserialize, Debug)]
+pub struct Wood {
+  pub from: Color,
+  pub to: Color,
+  #[serde(default="default_pattern_scale")]
+  pub scale: f64,
+  /// Rings per unit of distance from the axis, before scaling.
+  #[serde(default="default_rings")]
+  pub rings: f64,
+  #[serde(default="default_octaves")]
+  pub octaves: u32,
+  #[serde(default)]
+  pub turbulence: f64,
+  #[serde(default)]
+  pub space: PatternSpace,
+}
+
+/// The point a pattern is evaluated at, scaled.
+fn pattern_point(space: PatternSpace,
+                 scale: f64,
+                 texture_coords: &TextureCoords,
+                 position: &Point)
+                 -> Point {
+  let point = match space {
+    PatternSpace::Uv => {
+      Point {
+        x: texture_coords.x as f64,
+        y: texture_coords.y as f64,
+        z: 0.0,
+      }
+    }
+    PatternSpace::Position => *position,
+  };
+  Point {
+    x: point.x * scale,
+    y: point.y * scale,
+    z: point.z * scale,
+  }
+}
+
+fn mix(from: Color, to: Color, t: f64) -> Color {
+  let t = t.clamp(0.0, 1.0) as f32;
+  from * (1.0 - t) + to * t
+}
+
+impl Checker {
+  fn color(&self, point: &Point) -> Color {
+    // Surfaces often lie exactly on a cell boundary, such as a floor at y = -2. Nudging the
+    // point keeps them from flickering between two cells.
+    let cell = |v: f64| (v + 1e-4).floor() as i64;
+    if (cell(point.x) + cell(point.y) + cell(point.z)).rem_euclid(2) == 0 {
+      self.even
+    } else {
+      self.odd
+    }
+  }
+}
+
+impl Gradient {
+  fn color(&self, point: &Point) -> Color {
+    let axis = self.end - self.start;
+    let offset = *point - self.start;
+    let length_squared = axis.norm();
+    if length_squared <= 0.0 {
+      return self.to;
+    }
+    let t = match self.shape {
+      GradientShape::Linear => offset.dot(&axis) / length_squared,
+      GradientShape::Radial => (offset.norm() / length_squared).sqrt(),
+    };
+    mix(self.from, self.to, t)
+  }
 }
 
 #[derive(Deserialize)]
 pub enum Coloration {
   Color(Color),
   Texture(#[serde(deserialize_with="load_texture")]
-            DynamicImage),
+            Texture),
+  Checker(Checker),
+  Gradient(Gradient),
+  Noise(Noise),
+  Marble(Marble),
+  Wood(Wood),
 }
 impl fmt::Debug for Coloration {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match *self {
       Coloration::Color(ref c) => write!(f, "Color({:?})", c),
-      Coloration::Texture(_) => write!(f, "Texture"),
+      Coloration::Texture(ref t) => write!(f, "{:?}", t),
+      Coloration::Checker(ref c) => write!(f, "{:?}", c),
+      Coloration::Gradient(ref g) => write!(f, "{:?}", g),
+      Coloration::Noise(ref n) => write!(f, "{:?}", n),
+      Coloration::Marble(ref m) => write!(f, "{:?}", m),
+      Coloration::Wood(ref w) => write!(f, "{:?}", w),
     }
   }
 }
 impl Coloration {
-  pub fn color(&self, texture_coords: &TextureCoords) -> Color {
+  /// The surface color at a hit with the given texture coordinates and `position`; see
+  /// `Texture::color` for `footprint`. Procedural patterns are not filtered.
+  pub fn color(&self, texture_coords: &TextureCoords, position: &Point, footprint: f32) -> Color {
+    let point = |space, scale| pattern_point(space, scale, texture_coords, position);
     match *self {
       Coloration::Color(c) => c,
-      Coloration::Texture(ref texture) => {
-        let tex_x = wrap(texture_coords.x, texture.width());
-        let tex_y = wrap(texture_coords.y, texture.height());
-
-        Color::from_rgba(texture.get_pixel(tex_x, tex_y))
+      Coloration::Texture(ref texture) => texture.color(texture_coords, footprint),
+      Coloration::Checker(ref c) => c.color(&point(c.space, c.scale)),
+      Coloration::Gradient(ref g) => g.color(&point(g.space, 1.0)),
+      Coloration::Noise(ref n) => {
+        // Noise rarely strays far from zero, so it is stretched to use both colors fully.
+        let value = noise::fbm(&point(n.space, n.scale), n.octaves, n.persistence);
+        mix(n.from, n.to, 0.5 + value)
+      }
+      Coloration::Marble(ref m) => {
+        let p = point(m.space, m.scale);
+        let bend = m.turbulence * noise::turbulence(&p, m.octaves, 0.5);
+        mix(m.from, m.to, 0.5 + 0.5 * (p.x + bend).sin())
+      }
+      Coloration::Wood(ref w) => {
+        let p = point(w.space, w.scale);
+        let wobble = w.turbulence * noise::fbm(&p, w.octaves, 0.5);
+        let rings = p.x.hypot(p.y) * w.rings + wobble;
+        mix(w.from, w.to, rings - rings.floor())
       }
     }
   }
 }
 
-fn wrap(val: f32, bound: u32) -> u32 {
-  let signed_bound = bound as i32;
-  let float_coord = val * bound as f32;
-  let wrapped_coord = (float_coord as i32) % signed_bound;
-  if wrapped_coord < 0 {
-    (wrapped_coord + signed_bound) as u32
-  } else {
-    wrapped_coord as u32
-  }
-}
-
 
 //
 // ELEMENTS
@@ -227,6 +1068,9 @@ pub struct Plane {
   pub origin: Point,
   #[serde(deserialize_with="Vector3::deserialize_normalized")]
   pub normal: Vector3,
+  /// Places the element in the scene, as described on `Element`.
+  #[serde(default)]
+  pub transform: Option<Transform>,
   pub material: Material,
 }
 
@@ -235,20 +1079,182 @@ pub struct Plane {
 pub struct Sphere {
   pub center: Point,
   pub radius: f64,
+  /// Places the element in the scene, as described on `Element`.
+  #[serde(default)]
+  pub transform: Option<Transform>,
   pub material: Material,
 }
 
 
+#[derive(Deserialize, Debug)]
+pub struct Triangle {
+  pub vertices: [Point; 3],
+  #[serde(default)]
+  pub normals: Option<[Vector3; 3]>,
+  #[serde(default)]
+  pub texture_coords: Option<[TextureCoords; 3]>,
+  /// Places the element in the scene, as described on `Element`.
+  #[serde(default)]
+  pub transform: Option<Transform>,
+  pub material: Material,
+}
+
+
+/// A box, axis-aligned in object space.
+#[derive(Debug)]
+pub struct Cuboid {
+  pub min: Point,
+  pub max: Point,
+  /// Places the element in the scene, as described on `Element`.
+  pub transform: Option<Transform>,
+  pub material: Material,
+}
+
+/// How a box is written in a scene file.
+#[derive(Deserialize)]
+struct CuboidSource {
+  min: Point,
+  max: Point,
+  /// Rotation about the box's center, in degrees around the x, y and z axes (applied in that
+  /// order), before `transform`.
+  #[serde(default)]
+  rotation: Option<Vector3>,
+  #[serde(default)]
+  transform: Option<Transform>,
+  material: Material,
+}
+
+impl Deserialize for Cuboid {
+  fn deserialize<D>(deserializer: D) -> Result<Cuboid, D::Error>
+    where D: Deserializer
+  {
+    let source = CuboidSource::deserialize(deserializer)?;
+    let rotation = source.rotation.map(|degrees| {
+      let center = source.min + (source.max - source.min) * 0.5 - Point::zero();
+      Transform::translation(-center)
+        .then(&Transform::rotation_euler(&degrees))
+        .then(&Transform::translation(center))
+    });
+    let transform = match (rotation, source.transform) {
+      (Some(rotation), Some(transform)) => Some(rotation.then(&transform)),
+      (rotation, transform) => rotation.or(transform),
+    };
+    Ok(Cuboid {
+      min: source.min,
+      max: source.max,
+      transform,
+      material: source.material,
+    })
+  }
+}
+
+
+#[derive(Debug)]
+pub struct MeshFace {
+  pub vertices: [usize; 3],
+  pub normals: Option<[usize; 3]>,
+  pub texture_coords: Option<[usize; 3]>,
+}
+
+/// Indexed triangle geometry, with a BVH over its faces built on construction.
+#[derive(Debug)]
+pub struct MeshData {
+  pub positions: Vec<Point>,
+  pub normals: Vec<Vector3>,
+  pub texture_coords: Vec<TextureCoords>,
+  pub faces: Vec<MeshFace>,
+  pub bvh: Bvh,
+}
+impl MeshData {
+  pub fn new(positions: Vec<Point>,
+             normals: Vec<Vector3>,
+             texture_coords: Vec<TextureCoords>,
+             faces: Vec<MeshFace>)
+             -> MeshData {
+    let mut mesh = MeshData {
+      positions,
+      normals,
+      texture_coords,
+      faces,
+      bvh: Bvh::default(),
+    };
+    let boxes: Vec<_> = (0..mesh.faces.len())
+      .map(|face| {
+        let v = mesh.face_vertices(face);
+        Some(BoundingBox::empty().grow(&v[0]).grow(&v[1]).grow(&v[2]))
+      })
+      .collect();
+    mesh.bvh = Bvh::build(&boxes);
+    mesh
+  }
+
+  pub fn face_vertices(&self, face: usize) -> [Point; 3] {
+    let v = &self.faces[face].vertices;
+    [self.positions[v[0]], self.positions[v[1]], self.positions[v[2]]]
+  }
+
+  pub fn face_normals(&self, face: usize) -> Option<[Vector3; 3]> {
+    self.faces[face].normals.map(|n| [self.normals[n[0]], self.normals[n[1]], self.normals[n[2]]])
+  }
+
+  pub fn face_texture_coords(&self, face: usize) -> Option<[TextureCoords; 3]> {
+    self.faces[face]
+      .texture_coords
+      .map(|t| [self.texture_coords[t[0]], self.texture_coords[t[1]], self.texture_coords[t[2]]])
+  }
+}
+
+/// Loads the OBJ file at the deserialized path, which is relative to the scene file.
+pub fn load_obj<D>(deserializer: D) -> Result<MeshData, D::Error>
+  where D: Deserializer
+{
+  let path = scene_relative(&PathBuf::deserialize(deserializer)?);
+  obj::load(&path).map_err(|e| de::Error::custom(format!("{}: {}", path.display(), e)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Mesh {
+  /// Read from the OBJ file at this path, relative to the scene file.
+  #[serde(rename="obj", deserialize_with="load_obj")]
+  pub geometry: MeshData,
+  /// Places the element in the scene, as described on `Element`.
+  #[serde(default)]
+  pub transform: Option<Transform>,
+  pub material: Material,
+}
+
+
+/// Something in the scene that rays can hit.
+///
+/// Every element can have a `transform`, which places it in the scene. Its other fields,
+/// such as a sphere's center or a box's corners, are then in object space, and the
+/// transform maps them, and everything traced against them, into the world.
 #[derive(Deserialize, Debug)]
 pub enum Element {
   Sphere(Sphere),
   Plane(Plane),
+  Triangle(Triangle),
+  Mesh(Mesh),
+  Box(Cuboid),
 }
 impl Element {
   pub fn material(&self) -> &Material {
     match *self {
       Element::Sphere(ref s) => &s.material,
       Element::Plane(ref p) => &p.material,
+      Element::Triangle(ref t) => &t.material,
+      Element::Mesh(ref m) => &m.material,
+      Element::Box(ref b) => &b.material,
+    }
+  }
+
+  pub fn transform(&self) -> Option<&Transform> {
+    match *self {
+      Element::Sphere(ref s) => s.transform.as_ref(),
+      Element::Plane(ref p) => p.transform.as_ref(),
+      Element::Triangle(ref t) => t.transform.as_ref(),
+      Element::Mesh(ref m) => m.transform.as_ref(),
+      Element::Box(ref b) => b.transform.as_ref(),
     }
   }
 
@@ -261,6 +1267,174 @@ impl Element {
 }
 
 
+//
+// CAMERA
+//
+
+#[derive(Deserialize, Debug)]
+pub struct Camera {
+  pub position: Point,
+  pub look_at: Point,
+  #[serde(deserialize_with="Vector3::deserialize_normalized")]
+  pub up: Vector3,
+  /// Field of view in degrees, measured across the shorter side of the image.
+  pub fov: f64,
+}
+impl Camera {
+  /// Checks that the camera has a well-defined orientation: it must look somewhere other
+  /// than where it stands, and `up` must not lie along the direction it looks in.
+  pub fn validate(&self) -> Result<(), String> {
+    let forward = self.look_at - self.position;
+    if forward.length() == 0.0 {
+      return Err("camera look_at must differ from its position".to_string());
+    }
+    // Relative to the view distance, so the check holds at any scale. An up vector of zero
+    // normalizes to NaN, which fails it too.
+    if forward.cross(&self.up).length() > 1e-9 * forward.length() {
+      Ok(())
+    } else {
+      Err("camera up vector must not be parallel to the view direction".to_string())
+    }
+  }
+
+  fn deserialize_valid<D>(deserializer: D) -> Result<Camera, D::Error>
+    where D: Deserializer
+  {
+    let camera = Camera::deserialize(deserializer)?;
+    camera.validate().map_err(de::Error::custom)?;
+    Ok(camera)
+  }
+
+  /// Returns the camera's orthonormal basis as (right, up, forward).
+  pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+    let forward = (self.look_at - self.position).normalize();
+    let right = forward.cross(&self.up).normalize();
+    let up = right.cross(&forward);
+    (right, up, forward)
+  }
+}
+
+
+//
+// SAMPLING
+//
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub enum SamplePattern {
+  /// Samples at the centers of cells inside the filter footprint: a grid when the number of
+  /// samples is square, otherwise one cell in each row and column.
+  #[default]
+  Stratified,
+  /// One sample at a random position inside each of those cells.
+  Jittered,
+}
+
+/// The reconstruction filter used to weight the samples taken around each pixel.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub enum Filter {
+  #[default]
+  Box,
+  Tent,
+  Gaussian,
+}
+impl Filter {
+  /// How far from the pixel center, in pixels, samples are taken.
+  pub fn radius(&self) -> f64 {
+    match *self {
+      Filter::Box => 0.5,
+      Filter::Tent => 1.0,
+      Filter::Gaussian => 1.5,
+    }
+  }
+
+  pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+    match *self {
+      Filter::Box => 1.0,
+      Filter::Tent => (1.0 - dx.abs()).max(0.0) * (1.0 - dy.abs()).max(0.0),
+      Filter::Gaussian => {
+        let gaussian = |d: f64| (-2.0 * d * d).exp();
+        let r = self.radius();
+        (gaussian(dx) - gaussian(r)).max(0.0) * (gaussian(dy) - gaussian(r)).max(0.0)
+      }
+    }
+  }
+}
+
+
+//
+// INTEGRATOR
+//
+
+/// How the light arriving along each camera ray is computed.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub enum Integrator {
+  /// Whitted-style ray tracing: direct lighting plus perfect reflection and refraction.
+  #[default]
+  Whitted,
+  /// Monte Carlo path tracing, which adds indirect diffuse lighting. Needs many samples per
+  /// pixel to converge.
+  PathTracing,
+}
+
+
+//
+// TONE MAPPING
+//
+
+/// How linear radiance is compressed into the displayable range before gamma encoding.
+/// Every operator first scales the radiance by the scene's exposure.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapper {
+  /// Clips each channel to [0, 1], blowing out anything brighter.
+  #[default]
+  Exposure,
+  /// Reinhard's operator on luminance, L / (1 + L), which keeps hues in highlights.
+  Reinhard,
+  /// Narkowicz's fit of the ACES filmic curve, with a soft toe and shoulder per channel.
+  Aces,
+}
+impl ToneMapper {
+  /// Maps linear radiance to linear display values in [0, 1]. `exposure` is in stops.
+  pub fn apply(&self, color: Color, exposure: f32) -> Color {
+    let color = color * exposure.exp2();
+    match *self {
+      ToneMapper::Exposure => color.clamp(),
+      ToneMapper::Reinhard => {
+        let luminance = color.luminance();
+        if luminance <= 0.0 {
+          return Color::default();
+        }
+        (color * (1.0 + luminance).recip()).clamp()
+      }
+      ToneMapper::Aces => {
+        let curve = |x: f32| {
+          let x = x.max(0.0);
+          (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+        };
+        Color {
+          red: curve(color.red),
+          green: curve(color.green),
+          blue: curve(color.blue),
+        }
+        .clamp()
+      }
+    }
+  }
+}
+impl FromStr for ToneMapper {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<ToneMapper, String> {
+    match name.to_lowercase().as_str() {
+      "exposure" => Ok(ToneMapper::Exposure),
+      "reinhard" => Ok(ToneMapper::Reinhard),
+      "aces" => Ok(ToneMapper::Aces),
+      _ => Err(format!("unknown tone mapper '{}': expected exposure, reinhard or aces", name)),
+    }
+  }
+}
+
+
 //
 // SCENE
 //
@@ -269,18 +1443,162 @@ impl Element {
 pub struct Scene {
   pub width: u32,
   pub height: u32,
-  pub fov: f64,
+  #[serde(deserialize_with="Camera::deserialize_valid")]
+  pub camera: Camera,
   pub max_recursion_depth: u32,
   pub shadow_bias: f64,
-  pub elements: Vec<Element>,
+  pub elements: Elements,
   pub lights: Vec<Light>,
+  #[serde(default)]
+  pub integrator: Integrator,
+  /// Whether to trace light at sampled wavelengths rather than as RGB. Light spectra and
+  /// dispersion come out accurately, at the cost of some color noise.
+  #[serde(default)]
+  pub spectral: bool,
+  #[serde(default="default_shadow_samples")]
+  pub shadow_samples: u32,
+  #[serde(default="default_samples_per_pixel")]
+  pub samples_per_pixel: u32,
+  /// How many rays a Whitted render averages where a camera ray meets a rough reflective or
+  /// refractive surface. Deeper bounces follow a single ray each, so the cost stays linear.
+  #[serde(default="default_glossy_samples")]
+  pub glossy_samples: u32,
+  #[serde(default)]
+  pub sample_pattern: SamplePattern,
+  #[serde(default)]
+  pub filter: Filter,
+  #[serde(default)]
+  pub tone_mapper: ToneMapper,
+  /// Exposure adjustment in stops, applied before tone mapping.
+  #[serde(default)]
+  pub exposure: f32,
+  /// Whether to apply ordered dithering when quantizing to 8 bits.
+  #[serde(default)]
+  pub dither: bool,
+  #[serde(default="default_threads")]
+  pub threads: usize,
+}
+
+fn default_shadow_samples() -> u32 {
+  16
+}
+
+fn default_samples_per_pixel() -> u32 {
+  1
+}
+
+fn default_glossy_samples() -> u32 {
+  8
+}
+
+fn default_threads() -> usize {
+  thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
 }
+
 impl Scene {
+  /// Reads a scene from a JSON or YAML file, chosen by extension, and prepares it for
+  /// rendering.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, Error> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if !["json", "yml", "yaml"].contains(&extension) {
+      return Err(Error::UnsupportedFormat { path: path.to_path_buf() });
+    }
+
+    let file = File::open(path).map_err(|e| {
+        Error::Io {
+          path: path.to_path_buf(),
+          error: e,
+        }
+      })?;
+    let parse_error = |message: String| {
+      Error::Parse {
+        path: path.to_path_buf(),
+        message,
+      }
+    };
+    let directory = SceneDirectory::enter(path);
+    let parsed = if extension == "json" {
+      serde_json::from_reader(file).map_err(|e| parse_error(e.to_string()))
+    } else {
+      serde_yaml::from_reader(file).map_err(|e| parse_error(e.to_string()))
+    };
+    drop(directory);
+    let scene: Scene = parsed?;
+
+    scene.build_bvh();
+    Ok(scene)
+  }
+
+  /// Applies the scene's exposure and tone mapper to a rendered color.
+  pub fn tone_map(&self, color: Color) -> Color {
+    self.tone_mapper.apply(color, self.exposure)
+  }
+
+  /// Builds the acceleration structure used by `trace` now, rather than the first time the
+  /// scene is traced.
+  pub fn build_bvh(&self) {
+    self.elements.hierarchy();
+  }
+
   pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
     self.elements
-      .iter()
-      .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
-      .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+      .hierarchy()
+      .closest(ray, |i| self.elements[i].intersect(ray))
+      .and_then(|(i, hit)| Intersection::new(hit, &self.elements[i]))
+  }
+
+  /// Returns true if anything blocks `ray` closer than `max_distance`.
+  pub fn occluded(&self, ray: &Ray, max_distance: f64) -> bool {
+    let blocks = |e: &Element| e.intersect(ray).is_some_and(|hit| hit.distance < max_distance);
+    self.elements.hierarchy().any(ray, max_distance, |i| blocks(&self.elements[i]))
+  }
+}
+
+/// A scene's elements, along with the BVH that `Scene::trace` searches them with. They read
+/// like a slice, but can only be changed through `edit`, so the BVH always matches them.
+#[derive(Debug, Default)]
+pub struct Elements {
+  elements: Vec<Element>,
+  bvh: OnceLock<Bvh>,
+}
+impl Elements {
+  pub fn new(elements: Vec<Element>) -> Elements {
+    Elements {
+      elements,
+      bvh: OnceLock::new(),
+    }
+  }
+
+  /// Gives access to change the elements. The BVH is dropped, to be rebuilt for whatever
+  /// they become the next time the scene is traced.
+  pub fn edit(&mut self) -> &mut Vec<Element> {
+    self.bvh = OnceLock::new();
+    &mut self.elements
+  }
+
+  /// The BVH, built now if it hasn't been yet.
+  fn hierarchy(&self) -> &Bvh {
+    self.bvh.get_or_init(|| {
+      let boxes: Vec<_> = self.elements.iter().map(|e| e.bounding_box()).collect();
+      Bvh::build(&boxes)
+    })
+  }
+}
+
+impl Deref for Elements {
+  type Target = [Element];
+
+  fn deref(&self) -> &[Element] {
+    &self.elements
+  }
+}
+
+impl Deserialize for Elements {
+  fn deserialize<D>(deserializer: D) -> Result<Elements, D::Error>
+    where D: Deserializer
+  {
+    Vec::deserialize(deserializer).map(Elements::new)
   }
 }
 
@@ -291,20 +1609,23 @@ impl Scene {
 
 pub struct Intersection<'a> {
   pub distance: f64,
+  pub face: usize,
   pub element: &'a Element,
 
   _secret: (),
 }
 impl<'a> Intersection<'a> {
-  pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
-    if !distance.is_finite() {
-      panic!("Intersection must have a finite distance.");
+  /// Returns None if the hit is not at a finite distance.
+  pub fn new<'b>(hit: Hit, element: &'b Element) -> Option<Intersection<'b>> {
+    if !hit.distance.is_finite() {
+      return None;
     }
 
-    Intersection {
-      distance: distance,
+    Some(Intersection {
+      distance: hit.distance,
+      face: hit.face,
       element: element,
       _secret: (),
-    }
+    })
   }
 }
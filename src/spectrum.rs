@@ -0,0 +1,282 @@
+//! Spectral rendering: light sampled at a handful of wavelengths instead of as RGB.
+//!
+//! A spectral render still traces `Color`s, but each of their three channels holds the light
+//! at one of the sample's `Wavelengths`, so everything that multiplies colors channel by
+//! channel works unchanged. RGB colors from the scene are upsampled to spectra as they are
+//! read, and the finished sample is projected onto the CIE 1931 color matching functions and
+//! converted to linear sRGB.
+//!
+//! Lights without a spectrum of their own emit a blackbody spectrum at the temperature of
+//! sRGB's white point, tinted by their color, and the output is white balanced so that
+//! this spectrum comes out as white. Gray scenes look the same either way.
+
+use scene::Color;
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use std::sync::OnceLock;
+
+/// The range of wavelengths sampled, in nanometers. The eye is all but blind outside it.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// The color temperature, in kelvin, of the spectrum that RGB lights emit: that of D65, the
+/// sRGB white point.
+const WHITE_TEMPERATURE: f32 = 6504.0;
+
+/// Converts CIE XYZ to linear sRGB, with its D65 white point.
+const XYZ_TO_SRGB: [[f64; 3]; 3] = [[3.240_454_2, -1.537_138_5, -0.498_531_4],
+                                    [-0.969_266, 1.876_010_8, 0.041_556],
+                                    [0.055_643_4, -0.204_025_9, 1.057_225_2]];
+
+/// The CIE 1931 color matching functions at `wavelength`, from the multi-lobe Gaussian fit of
+/// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching
+/// Functions" (2013).
+pub fn cie_xyz(wavelength: f32) -> [f64; 3] {
+  let w = wavelength as f64;
+  // A Gaussian with a different width on either side of its peak.
+  let lobe = |peak: f64, below: f64, above: f64| {
+    let t = (w - peak) / if w < peak { below } else { above };
+    (-0.5 * t * t).exp()
+  };
+  [1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) -
+   0.065 * lobe(501.1, 20.4, 26.2),
+   0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+   1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8)]
+}
+
+/// Integrates `spectrum` against the color matching functions over the sampled range.
+fn integrate_xyz<F: Fn(f32) -> f64>(spectrum: F) -> [f64; 3] {
+  let mut xyz = [0.0; 3];
+  let mut wavelength = MIN_WAVELENGTH;
+  while wavelength <= MAX_WAVELENGTH {
+    let value = spectrum(wavelength);
+    for (total, cmf) in xyz.iter_mut().zip(cie_xyz(wavelength).iter()) {
+      *total += value * cmf;
+    }
+    wavelength += 1.0;
+  }
+  xyz
+}
+
+/// Planck's law for a blackbody at `temperature` kelvin, up to a constant factor.
+fn planck(wavelength: f32, temperature: f32) -> f64 {
+  let micrometers = wavelength as f64 * 1e-3;
+  let exponent = 14_387.769 / (micrometers * temperature as f64);
+  micrometers.powi(-5) / exponent.exp_m1()
+}
+
+/// The factor that brings `spectrum` to the luminance of a flat spectrum of one, or None if
+/// it has no visible light to scale.
+fn luminance_scale<F: Fn(f32) -> f64>(spectrum: F) -> Option<f64> {
+  let luminance = integrate_xyz(spectrum)[1];
+  if luminance.is_finite() && luminance > 0.0 {
+    Some(integrate_xyz(|_| 1.0)[1] / luminance)
+  } else {
+    None
+  }
+}
+
+/// The white that RGB lights emit: its scale, and its color in unbalanced linear sRGB.
+fn white_point() -> &'static (f64, [f64; 3]) {
+  static WHITE: OnceLock<(f64, [f64; 3])> = OnceLock::new();
+  WHITE.get_or_init(|| {
+    let scale = luminance_scale(|w| planck(w, WHITE_TEMPERATURE)).unwrap_or(1.0);
+    (scale, linear_srgb(integrate_xyz(|w| planck(w, WHITE_TEMPERATURE) * scale)))
+  })
+}
+
+/// The spectrum of white light, at `wavelength`.
+pub fn white(wavelength: f32) -> f32 {
+  (planck(wavelength, WHITE_TEMPERATURE) * white_point().0) as f32
+}
+
+/// Converts XYZ to linear sRGB, white balanced so that `white` comes out as white.
+fn xyz_to_rgb(xyz: [f64; 3]) -> Color {
+  let white = white_point().1;
+  let rgb = linear_srgb(xyz);
+  Color {
+    red: (rgb[0] / white[0]) as f32,
+    green: (rgb[1] / white[1]) as f32,
+    blue: (rgb[2] / white[2]) as f32,
+  }
+}
+
+fn linear_srgb(xyz: [f64; 3]) -> [f64; 3] {
+  let row = |r: &[f64; 3]| r[0] * xyz[0] + r[1] * xyz[1] + r[2] * xyz[2];
+  [row(&XYZ_TO_SRGB[0]), row(&XYZ_TO_SRGB[1]), row(&XYZ_TO_SRGB[2])]
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+  let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+  t * t * (3.0 - 2.0 * t)
+}
+
+/// The value at `wavelength` of a smooth spectrum for a linear RGB color. Each channel
+/// covers a band of the visible range, blue below about 495nm and red above about 585nm,
+/// and the bands add up to one everywhere, so gray stays flat and a color between zero and
+/// one gives a reflectance that is too.
+pub fn upsample(rgb: Color, wavelength: f32) -> f32 {
+  let blue = 1.0 - smoothstep(480.0, 510.0, wavelength);
+  let red = smoothstep(570.0, 600.0, wavelength);
+  let green = 1.0 - blue - red;
+  rgb.red * red + rgb.green * green + rgb.blue * blue
+}
+
+/// The wavelengths, in nanometers, that a spectral sample's three channels carry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths(pub [f32; 3]);
+
+impl Wavelengths {
+  /// Three wavelengths spread evenly over the sampled range, starting at a fraction `u` of
+  /// the way into it and wrapping around. Together they cover the range better than three
+  /// independent picks would.
+  pub fn sample(u: f64) -> Wavelengths {
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    let at = |i: f64| MIN_WAVELENGTH + ((u + i / 3.0) % 1.0) as f32 * range;
+    Wavelengths([at(0.0), at(1.0), at(2.0)])
+  }
+
+  /// The spectrum for an RGB reflectance, at each wavelength.
+  pub fn upsample(&self, rgb: Color) -> Color {
+    Color {
+      red: upsample(rgb, self.0[0]),
+      green: upsample(rgb, self.0[1]),
+      blue: upsample(rgb, self.0[2]),
+    }
+  }
+
+  /// The spectrum of white light, at each wavelength.
+  pub fn white(&self) -> Color {
+    Color {
+      red: white(self.0[0]),
+      green: white(self.0[1]),
+      blue: white(self.0[2]),
+    }
+  }
+
+  /// `spectrum`, at each wavelength.
+  pub fn evaluate(&self, spectrum: &Spectrum) -> Color {
+    Color {
+      red: spectrum.value(self.0[0]),
+      green: spectrum.value(self.0[1]),
+      blue: spectrum.value(self.0[2]),
+    }
+  }
+
+  /// Converts light measured at these wavelengths to linear sRGB. Each wavelength stands
+  /// for a third of the range, so averaged over many samples this converges on the color of
+  /// the whole spectrum.
+  pub fn to_rgb(&self, radiance: Color) -> Color {
+    let weight = ((MAX_WAVELENGTH - MIN_WAVELENGTH) / 3.0) as f64;
+    let mut xyz = [0.0; 3];
+    let values = [radiance.red, radiance.green, radiance.blue];
+    for (&wavelength, &value) in self.0.iter().zip(values.iter()) {
+      for (total, cmf) in xyz.iter_mut().zip(cie_xyz(wavelength).iter()) {
+        *total += value as f64 * cmf * weight;
+      }
+    }
+    xyz_to_rgb(xyz)
+  }
+}
+
+/// The shape of a light's emission spectrum. Only its shape matters: it is scaled to be as
+/// bright as white, so that a light's intensity keeps its meaning.
+#[derive(Deserialize, Debug, Clone)]
+enum SpectrumSource {
+  /// An ideal thermal emitter at `temperature` kelvin.
+  Blackbody { temperature: f32 },
+  /// Measured values at ascending `wavelengths`, interpolated linearly between them and
+  /// zero outside them.
+  Sampled {
+    wavelengths: Vec<f32>,
+    values: Vec<f32>,
+  },
+}
+
+impl SpectrumSource {
+  fn value(&self, wavelength: f32) -> f64 {
+    match *self {
+      SpectrumSource::Blackbody { temperature } => planck(wavelength, temperature),
+      SpectrumSource::Sampled { ref wavelengths, ref values } => {
+        let next = wavelengths.iter().position(|&w| w >= wavelength);
+        match next {
+          Some(0) if wavelengths[0] == wavelength => values[0] as f64,
+          Some(0) | None => 0.0,
+          Some(i) => {
+            let t = (wavelength - wavelengths[i - 1]) / (wavelengths[i] - wavelengths[i - 1]);
+            (values[i - 1] + (values[i] - values[i - 1]) * t) as f64
+          }
+        }
+      }
+    }
+  }
+
+  fn validate(&self) -> Result<(), String> {
+    match *self {
+      SpectrumSource::Blackbody { temperature } => {
+        if temperature > 0.0 {
+          Ok(())
+        } else {
+          Err("blackbody temperature must be positive".to_string())
+        }
+      }
+      SpectrumSource::Sampled { ref wavelengths, ref values } => {
+        if wavelengths.len() != values.len() {
+          Err("sampled spectrum needs as many values as wavelengths".to_string())
+        } else if wavelengths.windows(2).any(|pair| pair[0] >= pair[1]) {
+          Err("sampled spectrum wavelengths must be ascending".to_string())
+        } else if values.iter().any(|&v| v < 0.0) {
+          Err("sampled spectrum values must not be negative".to_string())
+        } else {
+          Ok(())
+        }
+      }
+    }
+  }
+}
+
+/// An emission spectrum, normalized to the luminance of a flat spectrum of one.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+  source: SpectrumSource,
+  scale: f64,
+  rgb: Color,
+}
+
+impl Spectrum {
+  /// A blackbody at `temperature` kelvin.
+  pub fn blackbody(temperature: f32) -> Option<Spectrum> {
+    Spectrum::new(SpectrumSource::Blackbody { temperature }).ok()
+  }
+
+  /// Values at ascending `wavelengths`, interpolated linearly.
+  pub fn sampled(wavelengths: Vec<f32>, values: Vec<f32>) -> Option<Spectrum> {
+    Spectrum::new(SpectrumSource::Sampled { wavelengths, values }).ok()
+  }
+
+  fn new(source: SpectrumSource) -> Result<Spectrum, String> {
+    source.validate()?;
+    let scale = luminance_scale(|w| source.value(w))
+      .ok_or_else(|| "spectrum must emit visible light".to_string())?;
+    let rgb = xyz_to_rgb(integrate_xyz(|w| source.value(w) * scale));
+    Ok(Spectrum { source, scale, rgb })
+  }
+
+  /// The spectrum's value at `wavelength`, in nanometers.
+  pub fn value(&self, wavelength: f32) -> f32 {
+    (self.source.value(wavelength) * self.scale) as f32
+  }
+
+  /// The spectrum's color in linear sRGB, for rendering without spectral sampling.
+  pub fn rgb(&self) -> Color {
+    self.rgb
+  }
+}
+
+impl Deserialize for Spectrum {
+  fn deserialize<D>(deserializer: D) -> Result<Spectrum, D::Error>
+    where D: Deserializer
+  {
+    Spectrum::new(SpectrumSource::deserialize(deserializer)?).map_err(de::Error::custom)
+  }
+}
@@ -0,0 +1,224 @@
+//! Affine transforms that place elements in the scene.
+
+use point::Point;
+use vector::Vector3;
+use rendering::Ray;
+use bvh::BoundingBox;
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use matrix::Matrix4;
+use quaternion::Quaternion;
+
+/// An affine transform. The inverse and the normal matrix are kept alongside it, since rays
+/// and normals are mapped far more often than the transform is built.
+#[derive(Debug, Clone)]
+pub struct Transform {
+  matrix: Matrix4,
+  inverse: Matrix4,
+  normal_matrix: Matrix4,
+}
+impl Transform {
+  /// Builds a transform from an affine matrix, or returns None if the matrix is projective
+  /// or cannot be inverted.
+  pub fn new(matrix: Matrix4) -> Option<Transform> {
+    if !matrix.is_affine() {
+      return None;
+    }
+    matrix.inverse().map(|inverse| {
+      Transform {
+        matrix,
+        inverse,
+        normal_matrix: matrix.normal_matrix(),
+      }
+    })
+  }
+
+  pub fn identity() -> Transform {
+    Transform {
+      matrix: Matrix4::identity(),
+      inverse: Matrix4::identity(),
+      normal_matrix: Matrix4::identity(),
+    }
+  }
+
+  pub fn translation(offset: Vector3) -> Transform {
+    Transform {
+      matrix: Matrix4::translation(offset),
+      inverse: Matrix4::translation(-offset),
+      normal_matrix: Matrix4::identity(),
+    }
+  }
+
+  /// Scales along each axis. Returns None if any factor is zero.
+  pub fn scaling(factors: Vector3) -> Option<Transform> {
+    Transform::new(Matrix4::scaling(factors))
+  }
+
+  /// Rotates by the given angles, in degrees, around the x, y and z axes in that order.
+  pub fn rotation_euler(degrees: &Vector3) -> Transform {
+    Transform::rotation(Matrix4::rotation_euler(degrees))
+  }
+
+  /// Rotates counterclockwise by `degrees` around `axis`, looking down the axis towards the
+  /// origin.
+  pub fn rotation_axis(axis: &Vector3, degrees: f64) -> Transform {
+    Transform::rotation(Matrix4::rotation_axis(axis, degrees))
+  }
+
+  /// Rotates by a quaternion, which is normalized first.
+  pub fn rotation_quaternion(rotation: &Quaternion) -> Transform {
+    Transform::rotation(rotation.normalize().to_matrix())
+  }
+
+  /// A rotation's inverse is its transpose, and it turns normals like any other direction.
+  fn rotation(matrix: Matrix4) -> Transform {
+    Transform {
+      matrix,
+      inverse: matrix.transpose(),
+      normal_matrix: matrix,
+    }
+  }
+
+  pub fn matrix(&self) -> &Matrix4 {
+    &self.matrix
+  }
+
+  /// The transform that applies `self` first and then `next`.
+  pub fn then(&self, next: &Transform) -> Transform {
+    Transform {
+      matrix: next.matrix * self.matrix,
+      inverse: self.inverse * next.inverse,
+      normal_matrix: next.normal_matrix * self.normal_matrix,
+    }
+  }
+
+  pub fn point_to_world(&self, point: &Point) -> Point {
+    self.matrix.transform_point(point)
+  }
+
+  pub fn point_to_local(&self, point: &Point) -> Point {
+    self.inverse.transform_point(point)
+  }
+
+  pub fn vector_to_world(&self, vector: &Vector3) -> Vector3 {
+    self.matrix.transform_vector(vector)
+  }
+
+  pub fn vector_to_local(&self, vector: &Vector3) -> Vector3 {
+    self.inverse.transform_vector(vector)
+  }
+
+  /// Maps a surface normal out of object space, by the matrix's `normal_matrix`.
+  pub fn normal_to_world(&self, normal: &Vector3) -> Vector3 {
+    self.normal_matrix.transform_vector(normal).normalize()
+  }
+
+  /// Maps a ray into object space, with its direction renormalized. Also returns how much
+  /// the direction was stretched: a distance along the local ray divided by it is the
+  /// distance along the original ray.
+  pub fn ray_to_local(&self, ray: &Ray) -> (Ray, f64) {
+    let direction = self.vector_to_local(&ray.direction);
+    let stretch = direction.length();
+    (Ray {
+       origin: self.point_to_local(&ray.origin),
+       direction: direction * stretch.recip(),
+     },
+     stretch)
+  }
+
+  /// The world-space box around a box in object space.
+  pub fn bounds_to_world(&self, bounds: &BoundingBox) -> BoundingBox {
+    let mut world = BoundingBox::empty();
+    for corner in 0..8 {
+      let point = Point {
+        x: if corner & 1 == 0 { bounds.min.x } else { bounds.max.x },
+        y: if corner & 2 == 0 { bounds.min.y } else { bounds.max.y },
+        z: if corner & 4 == 0 { bounds.min.z } else { bounds.max.z },
+      };
+      world = world.grow(&self.point_to_world(&point));
+    }
+    world
+  }
+}
+
+/// A scale factor for every axis, or one for each.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scale {
+  Uniform(f64),
+  Axes(Vector3),
+}
+
+#[derive(Deserialize)]
+struct AxisAngle {
+  axis: Vector3,
+  /// In degrees.
+  angle: f64,
+}
+
+/// How a transform is written in a scene file. Whichever parts are given are applied in
+/// the order listed here.
+#[derive(Deserialize)]
+struct TransformSource {
+  #[serde(default)]
+  scale: Option<Scale>,
+  /// Euler angles, in degrees around the x, y and z axes in that order.
+  #[serde(default)]
+  rotate: Option<Vector3>,
+  #[serde(default)]
+  axis_angle: Option<AxisAngle>,
+  /// A rotation given as a quaternion's w, x, y and z.
+  #[serde(default)]
+  quaternion: Option<Quaternion>,
+  #[serde(default)]
+  translate: Option<Vector3>,
+  /// Rows of an affine 4x4 matrix, applied to column vectors.
+  #[serde(default)]
+  matrix: Option<Matrix4>,
+}
+
+impl Deserialize for Transform {
+  fn deserialize<D>(deserializer: D) -> Result<Transform, D::Error>
+    where D: Deserializer
+  {
+    let source = TransformSource::deserialize(deserializer)?;
+    let mut transform = Transform::identity();
+    if let Some(scale) = source.scale {
+      let factors = match scale {
+        Scale::Uniform(factor) => Vector3::from_one(factor),
+        Scale::Axes(factors) => factors,
+      };
+      let scaling = Transform::scaling(factors)
+        .ok_or_else(|| de::Error::custom("transform scale must not be zero"))?;
+      transform = transform.then(&scaling);
+    }
+    if let Some(ref degrees) = source.rotate {
+      transform = transform.then(&Transform::rotation_euler(degrees));
+    }
+    if let Some(AxisAngle { ref axis, angle }) = source.axis_angle {
+      if axis.length() == 0.0 {
+        return Err(de::Error::custom("transform rotation axis must not be zero"));
+      }
+      transform = transform.then(&Transform::rotation_axis(axis, angle));
+    }
+    if let Some(ref rotation) = source.quaternion {
+      if rotation.length() == 0.0 {
+        return Err(de::Error::custom("transform quaternion must not be zero"));
+      }
+      transform = transform.then(&Transform::rotation_quaternion(rotation));
+    }
+    if let Some(offset) = source.translate {
+      transform = transform.then(&Transform::translation(offset));
+    }
+    if let Some(matrix) = source.matrix {
+      if !matrix.is_affine() {
+        return Err(de::Error::custom("transform matrix must be affine, with a last row of \
+                                      [0, 0, 0, 1]"));
+      }
+      let matrix = Transform::new(matrix)
+        .ok_or_else(|| de::Error::custom("transform matrix must be invertible"))?;
+      transform = transform.then(&matrix);
+    }
+    Ok(transform)
+  }
+}
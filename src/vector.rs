@@ -46,6 +46,17 @@ impl Vector3 {
     }
   }
 
+  /// Returns two unit vectors perpendicular to this (unit) vector and to each other.
+  pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+    let helper = if self.x.abs() > 0.9 {
+      Vector3 { x: 0.0, y: 1.0, z: 0.0 }
+    } else {
+      Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+    };
+    let tangent = self.cross(&helper).normalize();
+    (tangent, self.cross(&tangent))
+  }
+
   pub fn deserialize_normalized<D>(deserializer: D) -> Result<Vector3, D::Error>
     where D: Deserializer
   {